@@ -0,0 +1,11 @@
+#!/bin/sh
+# Runs the precision_drift scene with f32 and then with f64 solver math,
+# and fails when the final positions drift apart by more than 1% of the
+# particle spacing.
+set -e
+cd "$(dirname "$0")/../sph"
+dir=$(mktemp -d)
+trap 'rm -rf "$dir"' EXIT
+SPH_DRIFT_OUT="$dir/f32.txt" cargo test --release --lib precision_drift -- --ignored
+SPH_DRIFT_OUT="$dir/f64.txt" SPH_DRIFT_REF="$dir/f32.txt" \
+    cargo test --release --lib --features f64 precision_drift -- --ignored --nocapture
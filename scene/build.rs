@@ -1,7 +1,7 @@
 use anyhow::*;
 use glob::glob;
 use std::fs::{read_to_string, write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 struct ShaderData {
     src: String,
@@ -51,6 +51,20 @@ fn main() -> Result<()> {
         .into_iter()
         .collect::<Result<Vec<_>>>()?;
     let mut compiler = shaderc::Compiler::new().context("unable to create compiler")?;
+    // shared GLSL, pulled in with #include relative to the including shader
+    let mut options = shaderc::CompileOptions::new().context("unable to create compile options")?;
+    options.set_include_callback(|name, _, source, _| {
+        let path = Path::new(source).with_file_name(name);
+        read_to_string(&path)
+            .map(|content| shaderc::ResolvedInclude {
+                resolved_name: path.to_string_lossy().into_owned(),
+                content,
+            })
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    });
+    for include in glob("./src/**/*.glsl")? {
+        println!("cargo:rerun-if-changed={}", include?.display());
+    }
     for shader in shaders {
         println!(
             "cargo:rerun-if-changed={}",
@@ -61,7 +75,7 @@ fn main() -> Result<()> {
             shader.kind,
             &shader.src_path.to_str().unwrap(),
             "main",
-            None,
+            Some(&options),
         )?;
         println!("{:?}", shader.spv_path);
         write(shader.spv_path, compiled.as_binary_u8())?;
@@ -0,0 +1,131 @@
+use cgmath::{Deg, Matrix4, Point3, Vector2, Vector3};
+use utils::OPENGL_TO_WGPU_MATRIX;
+
+// Perspective camera orbiting the simulation box, which is drawn in
+// [-1, 1] on every axis.
+pub struct Camera {
+    pub eye: Point3<f32>,
+    pub target: Point3<f32>,
+    pub up: Vector3<f32>,
+    pub aspect: f32,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Camera {
+    pub fn new(aspect: f32) -> Self {
+        Camera {
+            eye: Point3::new(1.5, 1.5, 3.5),
+            target: Point3::new(0.0, 0.0, 0.0),
+            up: Vector3::unit_y(),
+            aspect,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+    pub fn view(&self) -> Matrix4<f32> {
+        Matrix4::look_at(self.eye, self.target, self.up)
+    }
+    pub fn projection(&self) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * cgmath::perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar)
+    }
+}
+
+// View and projection are kept apart, the sphere impostors are expanded
+// in view space before projecting.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct CameraUniform {
+    pub view: [[f32; 4]; 4],
+    pub proj: [[f32; 4]; 4],
+}
+
+unsafe impl bytemuck::Pod for CameraUniform {}
+unsafe impl bytemuck::Zeroable for CameraUniform {}
+
+impl CameraUniform {
+    pub fn new(camera: &Camera) -> Self {
+        CameraUniform {
+            view: camera.view().into(),
+            proj: camera.projection().into(),
+        }
+    }
+}
+
+// Orthographic camera for the 2D view. World units stay square whatever
+// the window shape, at zoom 1 the shorter window side spans [-1, 1].
+pub struct Camera2d {
+    pub center: Vector2<f32>,
+    pub zoom: f32,
+    pub aspect: f32,
+}
+
+impl Camera2d {
+    pub fn new(aspect: f32) -> Self {
+        Camera2d {
+            center: Vector2::new(0.0, 0.0),
+            zoom: 1.0,
+            aspect,
+        }
+    }
+    // half the visible width and height, in world units
+    pub fn half_extent(&self) -> Vector2<f32> {
+        let half = if self.aspect >= 1.0 {
+            Vector2::new(self.aspect, 1.0)
+        } else {
+            Vector2::new(1.0, 1.0 / self.aspect)
+        };
+        half / self.zoom
+    }
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        let half = self.half_extent();
+        let (min, max) = (self.center - half, self.center + half);
+        OPENGL_TO_WGPU_MATRIX * cgmath::ortho(min.x, max.x, min.y, max.y, -1.0, 1.0)
+    }
+    // drag by a number of pixels in a window `height` pixels tall, the
+    // world moves with the cursor
+    pub fn pan(&mut self, dx: f32, dy: f32, height: f32) {
+        let per_pixel = 2.0 * self.half_extent().y / height;
+        self.center += Vector2::new(-dx, dy) * per_pixel;
+    }
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.zoom = (self.zoom * factor).clamp(0.1, 100.0);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Camera2dUniform {
+    pub view_proj: [[f32; 4]; 4],
+}
+
+unsafe impl bytemuck::Pod for Camera2dUniform {}
+unsafe impl bytemuck::Zeroable for Camera2dUniform {}
+
+impl Camera2dUniform {
+    pub fn new(camera: &Camera2d) -> Self {
+        Camera2dUniform {
+            view_proj: camera.view_proj().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector4;
+
+    #[test]
+    fn world_units_stay_square() {
+        let mut camera = Camera2d::new(2.0);
+        let corner = camera.view_proj() * Vector4::new(1.0, 1.0, 0.0, 1.0);
+        assert_eq!((corner.x, corner.y), (0.5, 1.0));
+
+        camera.zoom_by(2.0);
+        camera.pan(-100.0, 0.0, 400.0);
+        let center = camera.view_proj() * Vector4::new(0.25, 0.0, 0.0, 1.0);
+        assert!(center.x.abs() < 1e-6 && center.y.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,128 @@
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Colormap {
+    Viridis,
+    Coolwarm,
+    Jet,
+}
+
+impl Colormap {
+    pub fn next(self) -> Self {
+        match self {
+            Colormap::Viridis => Colormap::Coolwarm,
+            Colormap::Coolwarm => Colormap::Jet,
+            Colormap::Jet => Colormap::Viridis,
+        }
+    }
+    // linear RGB, the same curves as colormap.glsl
+    pub fn rgb(self, t: f32) -> [f32; 3] {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Colormap::Viridis => {
+                const C: [[f32; 3]; 7] = [
+                    [0.277727, 0.00540734, 0.334100],
+                    [0.105093, 1.40461, 1.38459],
+                    [-0.330862, 0.214848, 0.0950952],
+                    [-4.63423, -5.79910, -19.3324],
+                    [6.22827, 14.1799, 56.6906],
+                    [4.77638, -13.7451, -65.3530],
+                    [-5.43546, 4.64585, 26.3124],
+                ];
+                let mut rgb = [0.0; 3];
+                for (k, v) in rgb.iter_mut().enumerate() {
+                    *v = C.iter().rev().fold(0.0, |acc, c| acc * t + c[k]);
+                }
+                rgb
+            }
+            Colormap::Coolwarm => {
+                let (cool, middle, warm) = ([0.230, 0.299, 0.754], [0.865, 0.865, 0.865], [0.706, 0.016, 0.150]);
+                let (a, b, s): ([f32; 3], [f32; 3], f32) =
+                    if t < 0.5 { (cool, middle, 2.0 * t) } else { (middle, warm, 2.0 * t - 1.0) };
+                [a[0] + (b[0] - a[0]) * s, a[1] + (b[1] - a[1]) * s, a[2] + (b[2] - a[2]) * s]
+            }
+            Colormap::Jet => {
+                let channel = |c: f32| (1.5 - (4.0 * t - c).abs()).clamp(0.0, 1.0);
+                [channel(3.0), channel(2.0), channel(1.0)]
+            }
+        }
+    }
+    pub fn color(self, value: f32, range: (f32, f32)) -> [f32; 3] {
+        self.rgb((value - range.0) / (range.1 - range.0))
+    }
+}
+
+// Auto follows the min and max of the current frame, Fixed keeps the
+// colors comparable between frames.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorRange {
+    Auto,
+    Fixed(f32, f32),
+}
+
+impl ColorRange {
+    pub fn resolve(self, values: &[f32]) -> (f32, f32) {
+        match self {
+            ColorRange::Auto => auto_range(values),
+            ColorRange::Fixed(min, max) => (min, max),
+        }
+    }
+}
+
+// min and max of the finite values, widened when they are all equal so
+// the shader never divides by zero
+pub fn auto_range(values: &[f32]) -> (f32, f32) {
+    let finite = values.iter().filter(|v| v.is_finite());
+    let (min, max) = finite.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &v| {
+        (min.min(v), max.max(v))
+    });
+    if min > max {
+        (0.0, 1.0)
+    } else if min == max {
+        (min - 0.5, max + 0.5)
+    } else {
+        (min, max)
+    }
+}
+
+// matches the Colors block in the fragment shaders, std140
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ColorUniform {
+    pub range: [f32; 2],
+    pub colormap: u32,
+    pub _padding: u32,
+}
+
+unsafe impl bytemuck::Pod for ColorUniform {}
+unsafe impl bytemuck::Zeroable for ColorUniform {}
+
+impl ColorUniform {
+    pub fn new(colormap: Colormap, range: (f32, f32)) -> Self {
+        ColorUniform {
+            range: [range.0, range.1],
+            colormap: colormap as u32,
+            _padding: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_range_skips_non_finite() {
+        assert_eq!(auto_range(&[2.0, f32::NAN, -1.0, f32::INFINITY]), (-1.0, 2.0));
+        assert_eq!(auto_range(&[3.0, 3.0]), (2.5, 3.5));
+        assert_eq!(auto_range(&[]), (0.0, 1.0));
+        assert_eq!(ColorRange::Fixed(0.0, 5.0).resolve(&[7.0]), (0.0, 5.0));
+    }
+
+    #[test]
+    fn colormap_ends() {
+        let close = |a: [f32; 3], b: [f32; 3]| a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 0.02);
+        assert!(close(Colormap::Viridis.rgb(0.0), [0.267, 0.005, 0.329]));
+        assert!(close(Colormap::Viridis.rgb(1.0), [0.993, 0.906, 0.144]));
+        assert!(close(Colormap::Coolwarm.color(5.0, (0.0, 10.0)), [0.865, 0.865, 0.865]));
+        assert!(close(Colormap::Jet.rgb(2.0), [0.5, 0.0, 0.0]));
+    }
+}
@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::fmt;
+
+// No adapter could draw to the window. Every backend group in `tried`
+// was asked for a default and then a low power adapter, and the adapters
+// in `refused` were found but would not give a device.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SetupError {
+    pub tried: Vec<wgpu::BackendBit>,
+    pub refused: Vec<String>,
+}
+
+fn backend_names(backends: wgpu::BackendBit) -> String {
+    let names = [
+        (wgpu::BackendBit::VULKAN, "Vulkan"),
+        (wgpu::BackendBit::METAL, "Metal"),
+        (wgpu::BackendBit::DX12, "DX12"),
+        (wgpu::BackendBit::DX11, "DX11"),
+        (wgpu::BackendBit::GL, "GL"),
+        (wgpu::BackendBit::BROWSER_WEBGPU, "WebGPU"),
+    ];
+    let names: Vec<&str> = names.iter().filter(|(bit, _)| backends.contains(*bit)).map(|(_, name)| *name).collect();
+    names.join(", ")
+}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tried: Vec<String> = self.tried.iter().map(|&backends| backend_names(backends)).collect();
+        write!(f, "no graphics adapter can draw to the window, tried {}", tried.join(", then "))?;
+        if !self.refused.is_empty() {
+            write!(f, "; {} refused to create a device", self.refused.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for SetupError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_backends_in_order() {
+        let error = SetupError {
+            tried: vec![wgpu::BackendBit::PRIMARY, wgpu::BackendBit::SECONDARY],
+            refused: vec!["llvmpipe".to_string()],
+        };
+        assert_eq!(
+            error.to_string(),
+            "no graphics adapter can draw to the window, tried Vulkan, Metal, DX12, WebGPU, then DX11, GL; \
+             llvmpipe refused to create a device"
+        );
+    }
+}
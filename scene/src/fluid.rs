@@ -0,0 +1,446 @@
+use crate::camera::CameraUniform;
+use crate::state::DEPTH_FORMAT;
+use utils::{InstanceLayout, Vertex};
+use wgpu::util::DeviceExt;
+
+const POSITION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+const THICKNESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+// matches the Fluid block in the fluid shaders, std140
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct FluidUniform {
+    pub radius: f32,
+    pub perspective: u32,
+    pub _padding: [u32; 2],
+}
+
+unsafe impl bytemuck::Pod for FluidUniform {}
+unsafe impl bytemuck::Zeroable for FluidUniform {}
+
+// Screen-space fluid surface. Particles are drawn as spheres into a view
+// space position target and, added up, into a thickness target. A
+// bilateral filter smooths the depth, then a fullscreen pass rebuilds
+// normals from it and shades the surface with absorption through the
+// thickness.
+pub struct FluidRenderer {
+    depth_pipeline: wgpu::RenderPipeline,
+    thickness_pipeline: wgpu::RenderPipeline,
+    filter_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    position: wgpu::TextureView,
+    filtered: wgpu::TextureView,
+    thickness: wgpu::TextureView,
+    filter_bind_group: wgpu::BindGroup,
+    composite_bind_group: wgpu::BindGroup,
+}
+
+impl FluidRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        instance_layout: InstanceLayout,
+    ) -> Self {
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fluid Camera Buffer"),
+            size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Fluid Camera Bind Group"),
+            layout: camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(camera_buffer.slice(..)),
+            }],
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fluid Buffer"),
+            contents: bytemuck::cast_slice(&[FluidUniform {
+                radius: 1.0,
+                perspective: 0,
+                _padding: [0; 2],
+            }]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Fluid Sampler"),
+            ..Default::default()
+        });
+        let layout = FluidRenderer::layout(device);
+
+        let sphere_vs = match instance_layout {
+            InstanceLayout::Full => device.create_shader_module(wgpu::include_spirv!("./shaders/sphere.vert.spv")),
+            InstanceLayout::Compact => {
+                device.create_shader_module(wgpu::include_spirv!("./shaders/sphere_compact.vert.spv"))
+            }
+        };
+        let fullscreen_vs =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/fullscreen.vert.spv"));
+        let depth_fs =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/fluid_depth.frag.spv"));
+        let thickness_fs =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/fluid_thickness.frag.spv"));
+        let filter_fs =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/fluid_filter.frag.spv"));
+        let composite_fs =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/fluid_composite.frag.spv"));
+
+        let depth_pipeline = FluidRenderer::pipeline(
+            device,
+            "Fluid Depth Pipeline",
+            camera_bind_group_layout,
+            (&sphere_vs, &depth_fs),
+            POSITION_FORMAT,
+            Pass::Depth,
+            instance_layout,
+        );
+        let thickness_pipeline = FluidRenderer::pipeline(
+            device,
+            "Fluid Thickness Pipeline",
+            camera_bind_group_layout,
+            (&sphere_vs, &thickness_fs),
+            THICKNESS_FORMAT,
+            Pass::Thickness,
+            instance_layout,
+        );
+        let filter_pipeline = FluidRenderer::pipeline(
+            device,
+            "Fluid Filter Pipeline",
+            &layout,
+            (&fullscreen_vs, &filter_fs),
+            POSITION_FORMAT,
+            Pass::Fullscreen,
+            instance_layout,
+        );
+        let composite_pipeline = FluidRenderer::pipeline(
+            device,
+            "Fluid Composite Pipeline",
+            &layout,
+            (&fullscreen_vs, &composite_fs),
+            sc_desc.format,
+            Pass::Fullscreen,
+            instance_layout,
+        );
+
+        let position = FluidRenderer::target(device, sc_desc, POSITION_FORMAT);
+        let filtered = FluidRenderer::target(device, sc_desc, POSITION_FORMAT);
+        let thickness = FluidRenderer::target(device, sc_desc, THICKNESS_FORMAT);
+        let filter_bind_group =
+            FluidRenderer::bind_group(device, &layout, &uniform_buffer, &sampler, &position, &thickness);
+        let composite_bind_group =
+            FluidRenderer::bind_group(device, &layout, &uniform_buffer, &sampler, &filtered, &thickness);
+        FluidRenderer {
+            depth_pipeline,
+            thickness_pipeline,
+            filter_pipeline,
+            composite_pipeline,
+            camera_buffer,
+            camera_bind_group,
+            uniform_buffer,
+            sampler,
+            layout,
+            position,
+            filtered,
+            thickness,
+            filter_bind_group,
+            composite_bind_group,
+        }
+    }
+
+    // the targets follow the swap chain size
+    pub fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {
+        self.position = FluidRenderer::target(device, sc_desc, POSITION_FORMAT);
+        self.filtered = FluidRenderer::target(device, sc_desc, POSITION_FORMAT);
+        self.thickness = FluidRenderer::target(device, sc_desc, THICKNESS_FORMAT);
+        self.filter_bind_group = FluidRenderer::bind_group(
+            device,
+            &self.layout,
+            &self.uniform_buffer,
+            &self.sampler,
+            &self.position,
+            &self.thickness,
+        );
+        self.composite_bind_group = FluidRenderer::bind_group(
+            device,
+            &self.layout,
+            &self.uniform_buffer,
+            &self.sampler,
+            &self.filtered,
+            &self.thickness,
+        );
+    }
+
+    // `camera` places the spheres in view space, for the 2D view that is
+    // an identity view and the orthographic projection. `radius` is the
+    // particle radius in view units.
+    pub fn update(&self, queue: &wgpu::Queue, camera: &CameraUniform, radius: f32, perspective: bool) {
+        queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[*camera]));
+        let fluid = FluidUniform {
+            radius,
+            perspective: perspective as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[fluid]));
+    }
+
+    // particles are drawn from the quad in `mesh` with one instance each,
+    // the surface ends up on `frame` over a white background
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        mesh: (&wgpu::Buffer, &wgpu::Buffer, u32),
+        instances: (&wgpu::Buffer, u32),
+    ) {
+        let (vertex_buffer, index_buffer, num_indices) = mesh;
+        let (instance_buffer, num_instances) = instances;
+        let clear = wgpu::Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+            a: 0.0,
+        };
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[attachment(&self.position, clear)],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: depth,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.depth_pipeline);
+            pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            pass.set_index_buffer(index_buffer.slice(..));
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            pass.draw_indexed(0..num_indices, 0, 0..num_instances);
+        }
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[attachment(&self.thickness, clear)],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.thickness_pipeline);
+            pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            pass.set_index_buffer(index_buffer.slice(..));
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            pass.draw_indexed(0..num_indices, 0, 0..num_instances);
+        }
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[attachment(&self.filtered, clear)],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.filter_pipeline);
+            pass.set_bind_group(0, &self.filter_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        {
+            let white = wgpu::Color {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+                a: 1.0,
+            };
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[attachment(frame, white)],
+                depth_stencil_attachment: None,
+            });
+            pass.set_pipeline(&self.composite_pipeline);
+            pass.set_bind_group(0, &self.composite_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+
+    fn layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::SampledTexture {
+                dimension: wgpu::TextureViewDimension::D2,
+                component_type: wgpu::TextureComponentType::Float,
+                multisampled: false,
+            },
+            count: None,
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Fluid Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer {
+                        dynamic: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                    count: None,
+                },
+                texture(3),
+            ],
+        })
+    }
+
+    fn bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        position: &wgpu::TextureView,
+        thickness: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Fluid Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(uniform_buffer.slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(position),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(thickness),
+                },
+            ],
+        })
+    }
+
+    fn target(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        format: wgpu::TextureFormat,
+    ) -> wgpu::TextureView {
+        device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Fluid Target"),
+                size: wgpu::Extent3d {
+                    width: sc_desc.width,
+                    height: sc_desc.height,
+                    depth: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn pipeline(
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::BindGroupLayout,
+        shaders: (&wgpu::ShaderModule, &wgpu::ShaderModule),
+        format: wgpu::TextureFormat,
+        pass: Pass,
+        instance_layout: InstanceLayout,
+    ) -> wgpu::RenderPipeline {
+        let blend = match pass {
+            Pass::Thickness => wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            _ => wgpu::BlendDescriptor::REPLACE,
+        };
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[layout],
+            push_constant_ranges: &[],
+        });
+        let vertex_buffers = [Vertex::desc(), instance_layout.desc()];
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&pipeline_layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: shaders.0,
+                entry_point: "main",
+            },
+            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+                module: shaders.1,
+                entry_point: "main",
+            }),
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 0,
+                depth_bias_slope_scale: 0.0,
+                depth_bias_clamp: 0.0,
+                clamp_depth: false,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[wgpu::ColorStateDescriptor {
+                format,
+                color_blend: blend.clone(),
+                alpha_blend: blend,
+                write_mask: wgpu::ColorWrite::ALL,
+            }],
+            depth_stencil_state: if pass == Pass::Depth {
+                Some(wgpu::DepthStencilStateDescriptor {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilStateDescriptor::default(),
+                })
+            } else {
+                None
+            },
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint16,
+                vertex_buffers: if pass == Pass::Fullscreen { &[] } else { &vertex_buffers },
+            },
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+        })
+    }
+}
+
+// The particle passes read the quad and instance buffers. Only the depth
+// pass tests depth, every particle on a pixel adds to the thickness.
+#[derive(Copy, Clone, PartialEq)]
+enum Pass {
+    Depth,
+    Thickness,
+    Fullscreen,
+}
+
+fn attachment(view: &wgpu::TextureView, clear: wgpu::Color) -> wgpu::RenderPassColorAttachmentDescriptor<'_> {
+    wgpu::RenderPassColorAttachmentDescriptor {
+        attachment: view,
+        resolve_target: None,
+        ops: wgpu::Operations {
+            load: wgpu::LoadOp::Clear(clear),
+            store: true,
+        },
+    }
+}
@@ -0,0 +1,77 @@
+use utils::{Instance, InstanceLayout};
+
+const MIN_CAPACITY: usize = 256;
+
+// Instance data kept on the GPU between frames and rewritten in place.
+// The buffer is only replaced when the particles outgrow it, and then at
+// least doubles so emitters don't cause a new one every frame.
+pub struct InstanceBuffer {
+    pub buffer: wgpu::Buffer,
+    pub layout: InstanceLayout,
+    capacity: usize,
+    len: usize,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, layout: InstanceLayout, capacity: usize) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        InstanceBuffer {
+            buffer: InstanceBuffer::create(device, layout, capacity),
+            layout,
+            capacity,
+            len: 0,
+        }
+    }
+    fn create(device: &wgpu::Device, layout: InstanceLayout, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * layout.size()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &[Instance]) {
+        if instances.len() > self.capacity {
+            self.capacity = grown_capacity(self.capacity, instances.len());
+            self.buffer = InstanceBuffer::create(device, self.layout, self.capacity);
+        }
+        self.len = instances.len();
+        if !instances.is_empty() {
+            let mut data = Vec::with_capacity(instances.len() * self.layout.size());
+            for instance in instances {
+                data.extend_from_slice(instance.to_raw(self.layout).bytes());
+            }
+            queue.write_buffer(&self.buffer, 0, &data);
+        }
+    }
+}
+
+pub fn grown_capacity(capacity: usize, needed: usize) -> usize {
+    let mut capacity = capacity.max(MIN_CAPACITY);
+    while capacity < needed {
+        capacity *= 2;
+    }
+    capacity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capacity_doubles_past_the_need() {
+        assert_eq!(grown_capacity(0, 10), MIN_CAPACITY);
+        assert_eq!(grown_capacity(256, 257), 512);
+        assert_eq!(grown_capacity(512, 3000), 4096);
+        assert_eq!(grown_capacity(4096, 100), 4096);
+    }
+}
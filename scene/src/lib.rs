@@ -1,109 +1,105 @@
+// is_multiple_of needs Rust 1.87 and div_ceil 1.73
+#![allow(clippy::manual_is_multiple_of, clippy::manual_div_ceil)]
+
+mod camera;
+mod colors;
+mod error;
+mod fluid;
+mod instances;
+mod offscreen;
+mod raster;
 mod state;
 
-pub use crate::state::State;
-use sph::Sph;
+pub use crate::camera::{Camera, Camera2d, Camera2dUniform, CameraUniform};
+pub use crate::colors::{auto_range, ColorRange, ColorUniform, Colormap};
+pub use crate::error::SetupError;
+pub use crate::fluid::{FluidRenderer, FluidUniform};
+pub use crate::instances::{grown_capacity, InstanceBuffer};
+pub use crate::offscreen::{write_png, Offscreen, Recorder, OFFSCREEN_FORMAT};
+pub use crate::raster::{max_difference, srgb, CpuRenderer};
+pub use crate::state::{Draw, State};
+use cgmath::SquareMatrix;
+use sph::{Field, Simulation};
 use std::iter;
-use utils::Vertex;
+use utils::{InstanceLayout, Vertex};
 use winit::{event::*, window::Window};
 
+// particle billboard, the fragment shaders cut the circle or sphere out of it
 const VERTICES: &[Vertex] = &[
     Vertex {
-        position: [0.0, 0.0],
-    },
-    Vertex {
-        position: [0.0, 1.0]
-    },
-    Vertex {
-        position: [1.0, 0.0]
-    },
-    Vertex {
-        position: [0.0, -1.0]
-    },
-    Vertex {
-        position: [-1.0, 0.0]
-    },
-];
-/*const VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [0.0, 0.0],
-    },
-    Vertex {
-        position: [0.0, 0.707],
-    },
-    Vertex {
-        position: [0.25, 0.661],
-    },
-    Vertex {
-        position: [0.5, 0.5],
-    },
-    Vertex {
-        position: [0.661, 0.25],
-    },
-    Vertex {
-        position: [0.707, 0.0],
-    },
-    Vertex {
-        position: [0.661, -0.25],
+        position: [-1.0, -1.0],
     },
     Vertex {
-        position: [0.5, -0.5],
+        position: [1.0, -1.0],
     },
     Vertex {
-        position: [0.25, -0.661],
+        position: [1.0, 1.0],
     },
     Vertex {
-        position: [0.0, -0.707],
-    },
-    Vertex {
-        position: [-0.25, -0.661],
-    },
-    Vertex {
-        position: [-0.5, -0.5],
-    },
-    Vertex {
-        position: [-0.661, -0.25],
-    },
-    Vertex {
-        position: [-0.707, 0.0],
-    },
-    Vertex {
-        position: [-0.661, 0.25],
-    },
-    Vertex {
-        position: [-0.5, 0.5],
-    },
-    Vertex {
-        position: [-0.25, 0.661],
+        position: [-1.0, 1.0],
     },
 ];
 
-const INDICES: &[u16] = &[
-    0, 1, 2, 0, 2, 3, 0, 3, 4, 0, 4, 5, 0, 5, 6, 0, 6, 7, 0, 7, 8, 0, 8, 9, 0, 9, 10, 0, 10, 11, 0,
-    11, 12, 0, 12, 13, 0, 13, 14, 0, 14, 15, 0, 15, 16, 0, 16, 1,
-];
-*/
-const INDICES: &[u16] = &[
-    0,1,2,
-    0,2,3,
-    0,3,4,
-    0,4,1,
-];
+const INDICES: &[u16] = &[0, 1, 2, 0, 2, 3];
 
 impl State {
-    pub async fn new(window: &Window, model: Sph) -> Self {
-        let instance = &State::instance();
-        let surface = State::surface(instance, window);
-        let (device, queue) = State::device_queue(instance, &surface).await;
+    // the compact instance layout sends a fifth of the data per particle,
+    // but leaves out rotation and the surface flags
+    pub async fn new(
+        window: &Window,
+        model: Box<dyn Simulation>,
+        instance_layout: InstanceLayout,
+    ) -> Result<Self, SetupError> {
+        let (surface, device, queue) = State::device_queue(window).await?;
         let size = State::size(window);
         let sc_desc = State::sc_desc(&size);
         let swap_chain = State::swap_chain(&device, &surface, &sc_desc);
-        let render_pipeline = State::render_pipeline(&device, &sc_desc);
+        let (colormap, color_range) = (Colormap::Viridis, ColorRange::Auto);
+        let color_buffer = State::color_buffer(&device, &ColorUniform::new(colormap, (0.0, 1.0)));
+        let color_bind_group_layout = State::color_bind_group_layout(&device);
+        let color_bind_group =
+            State::color_bind_group(&device, &color_bind_group_layout, &color_buffer);
         let vertex_buffer = State::vertex_buffer(&device, VERTICES);
         let index_buffer = State::index_buffer(&device, INDICES);
         let num_indices = State::num_indices(INDICES);
+        let aspect = sc_desc.width as f32 / sc_desc.height as f32;
+        let camera = Camera::new(aspect);
+        let camera_buffer = State::camera_buffer(&device, &camera);
+        let camera_bind_group_layout = State::camera_bind_group_layout(&device);
+        let camera_bind_group =
+            State::camera_bind_group(&device, &camera_bind_group_layout, &camera_buffer);
+        let camera2d = Camera2d::new(aspect);
+        let camera2d_buffer = State::camera2d_buffer(&device, &camera2d);
+        let camera2d_bind_group =
+            State::camera_bind_group(&device, &camera_bind_group_layout, &camera2d_buffer);
+        let render_pipeline = State::render_pipeline(
+            &device,
+            &sc_desc,
+            &camera_bind_group_layout,
+            &color_bind_group_layout,
+            instance_layout,
+        );
+        let shaded_pipeline = State::shaded_pipeline(
+            &device,
+            &sc_desc,
+            &camera_bind_group_layout,
+            &color_bind_group_layout,
+            instance_layout,
+        );
+        let sphere_pipeline = State::sphere_pipeline(
+            &device,
+            &sc_desc,
+            &camera_bind_group_layout,
+            &color_bind_group_layout,
+            instance_layout,
+        );
+        let contour_pipeline = State::contour_pipeline(&device, &sc_desc, &camera_bind_group_layout);
+        let fluid_renderer = FluidRenderer::new(&device, &sc_desc, &camera_bind_group_layout, instance_layout);
+        let (depth_texture, depth_view) = State::depth_texture(&device, &sc_desc);
         let instances = model.instances();
-        let instance_buffer = State::instance_buffer(&device, &instances);
-        Self {
+        let mut instance_buffer = InstanceBuffer::new(&device, instance_layout, instances.len());
+        instance_buffer.write(&device, &queue, &instances);
+        Ok(Self {
             surface,
             device,
             queue,
@@ -111,42 +107,189 @@ impl State {
             sc_desc,
             swap_chain,
             render_pipeline,
+            shaded_pipeline,
             vertex_buffer,
             index_buffer,
             num_indices,
+            sphere_pipeline,
+            camera,
+            camera_buffer,
+            camera_bind_group,
+            camera2d,
+            camera2d_buffer,
+            camera2d_bind_group,
+            cursor: None,
+            dragging: false,
+            depth_texture,
+            depth_view,
+            color_field: Field::Speed,
+            colormap,
+            color_range,
+            color_buffer,
+            color_bind_group,
             instances,
             instance_buffer,
             model,
-        }
+            running: true,
+            shaded: false,
+            fluid: false,
+            fluid_renderer,
+            contour: false,
+            contour_pipeline,
+        })
     }
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         self.sc_desc.width = new_size.width;
         self.sc_desc.height = new_size.height;
         self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+        let (depth_texture, depth_view) = State::depth_texture(&self.device, &self.sc_desc);
+        self.depth_texture = depth_texture;
+        self.depth_view = depth_view;
+        self.fluid_renderer.resize(&self.device, &self.sc_desc);
+        self.camera.aspect = new_size.width as f32 / new_size.height as f32;
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[CameraUniform::new(&self.camera)]),
+        );
+        self.camera2d.aspect = self.camera.aspect;
+        self.write_camera2d();
+    }
+    pub fn write_camera2d(&mut self) {
+        self.queue.write_buffer(
+            &self.camera2d_buffer,
+            0,
+            bytemuck::cast_slice(&[Camera2dUniform::new(&self.camera2d)]),
+        );
     }
-    pub fn input(&mut self, _event: &WindowEvent) -> bool {
-        false
+    // The mouse wheel zooms and dragging with the left button pans the 2D
+    // view. 1-8 color by speed, density, pressure, phase, shear rate,
+    // temperature, vorticity or the free surface flag, C cycles the
+    // colormap, R freezes or releases the color range, S switches 2D
+    // particles between flat circles and shaded spheres, F toggles the
+    // screen-space fluid surface and M the filled 2D outline.
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        let key = match event {
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / 50.0,
+                };
+                self.camera2d.zoom_by(1.1f32.powf(lines));
+                self.write_camera2d();
+                return true;
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+                return true;
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some(last)) = (self.dragging, self.cursor) {
+                    let (dx, dy) = (position.x - last.x, position.y - last.y);
+                    self.camera2d.pan(dx as f32, dy as f32, self.size.height as f32);
+                    self.write_camera2d();
+                }
+                self.cursor = Some(*position);
+                return self.dragging;
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => *key,
+            _ => return false,
+        };
+        match key {
+            VirtualKeyCode::Key1 => self.color_by(Field::Speed),
+            VirtualKeyCode::Key2 => self.color_by(Field::Density),
+            VirtualKeyCode::Key3 => self.color_by(Field::Pressure),
+            VirtualKeyCode::Key4 => self.color_by(Field::Phase),
+            VirtualKeyCode::Key5 => self.color_by(Field::ShearRate),
+            VirtualKeyCode::Key6 => self.color_by(Field::Temperature),
+            VirtualKeyCode::Key7 => self.color_by(Field::Vorticity),
+            VirtualKeyCode::Key8 => self.color_by(Field::Surface),
+            VirtualKeyCode::Key9 => self.color_by(Field::NormalX),
+            VirtualKeyCode::Key0 => self.color_by(Field::NormalY),
+            VirtualKeyCode::C => self.colormap = self.colormap.next(),
+            VirtualKeyCode::S => self.shaded = !self.shaded,
+            VirtualKeyCode::F => self.fluid = !self.fluid,
+            VirtualKeyCode::M => self.contour = !self.contour,
+            VirtualKeyCode::R => {
+                self.color_range = match self.color_range {
+                    ColorRange::Auto => {
+                        let scalars: Vec<f32> = self.instances.iter().map(|i| i.scalar).collect();
+                        let (min, max) = auto_range(&scalars);
+                        ColorRange::Fixed(min, max)
+                    }
+                    ColorRange::Fixed(..) => ColorRange::Auto,
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+    // a new field has a different scale, the range goes back to auto
+    pub fn color_by(&mut self, field: Field) {
+        self.color_field = field;
+        self.color_range = ColorRange::Auto;
     }
     pub fn update(&mut self) {}
     pub fn render(&mut self) -> Result<(), wgpu::SwapChainError> {
-        self.model.timestep();
+        // a failed step leaves the last state on screen
+        if self.running {
+            if let Err(e) = self.model.timestep() {
+                log::error!("simulation stopped: {}", e);
+                self.running = false;
+            }
+        }
         self.instances = self.model.instances();
-        let _scale_ratio = self.size.width as f32 / self.size.height as f32;
-        /*self.instances = self.instances.iter_mut().map(|p| Instance {
-            position: p.position,
-            rotation: p.rotation,
-            scale: Vector2::new(p.scale.x / scale_ratio, p.scale.y),
-        }).collect::<Vec<_>>(); */
-        self.instance_buffer = State::instance_buffer(&self.device, &self.instances);
+        let scalars = self.model.scalars(self.color_field);
+        for (instance, &scalar) in self.instances.iter_mut().zip(&scalars) {
+            instance.scalar = scalar;
+        }
+        self.instance_buffer.write(&self.device, &self.queue, &self.instances);
+        let colors = ColorUniform::new(self.colormap, self.color_range.resolve(&scalars));
+        self.queue.write_buffer(&self.color_buffer, 0, bytemuck::cast_slice(&[colors]));
 
+        let is_3d = self.model.is_3d();
+        let mesh = if self.contour && !is_3d { self.model.surface_mesh() } else { vec![] };
+        let mesh_buffer = if mesh.is_empty() { None } else { Some(State::vertex_buffer(&self.device, &mesh)) };
+        let draw = Draw::choose(is_3d, self.shaded, mesh_buffer.is_some(), !self.instances.is_empty());
         let frame = self.swap_chain.get_current_frame()?.output;
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Render Encoder"),
             });
-        {
+        if self.fluid && !self.instances.is_empty() {
+            // the 2D view has no view matrix, world space is view space
+            let camera = if is_3d {
+                CameraUniform::new(&self.camera)
+            } else {
+                CameraUniform {
+                    view: cgmath::Matrix4::identity().into(),
+                    proj: self.camera2d.view_proj().into(),
+                }
+            };
+            let radius = self.instances[0].scale.x;
+            self.fluid_renderer.update(&self.queue, &camera, radius, is_3d);
+            self.fluid_renderer.render(
+                &mut encoder,
+                &frame.view,
+                &self.depth_view,
+                (&self.vertex_buffer, &self.index_buffer, self.num_indices),
+                (&self.instance_buffer.buffer, self.instances.len() as u32),
+            );
+        } else {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
                     attachment: &frame.view,
@@ -161,13 +304,45 @@ impl State {
                         store: true,
                     },
                 }],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: if draw.depth() {
+                    Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                        attachment: &self.depth_view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    })
+                } else {
+                    None
+                },
             });
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            render_pass.set_index_buffer(self.index_buffer.slice(..));
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
+            // emitters and sinks change the particle count, which may drop to zero
+            match draw {
+                Draw::Nothing => {}
+                Draw::Contour => {
+                    if let Some(mesh_buffer) = &mesh_buffer {
+                        render_pass.set_pipeline(&self.contour_pipeline);
+                        render_pass.set_bind_group(0, &self.camera2d_bind_group, &[]);
+                        render_pass.set_vertex_buffer(0, mesh_buffer.slice(..));
+                        render_pass.draw(0..mesh.len() as u32, 0..1);
+                    }
+                }
+                Draw::Flat | Draw::Shaded | Draw::Spheres => {
+                    let (pipeline, camera) = match draw {
+                        Draw::Spheres => (&self.sphere_pipeline, &self.camera_bind_group),
+                        Draw::Shaded => (&self.shaded_pipeline, &self.camera2d_bind_group),
+                        _ => (&self.render_pipeline, &self.camera2d_bind_group),
+                    };
+                    render_pass.set_pipeline(pipeline);
+                    render_pass.set_bind_group(0, camera, &[]);
+                    render_pass.set_bind_group(1, &self.color_bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                    render_pass.set_index_buffer(self.index_buffer.slice(..));
+                    render_pass.set_vertex_buffer(1, self.instance_buffer.buffer.slice(..));
+                    render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as _);
+                }
+            }
         }
         self.queue.submit(iter::once(encoder.finish()));
 
@@ -0,0 +1,343 @@
+use crate::camera::{Camera, Camera2d};
+use crate::colors::{ColorRange, ColorUniform, Colormap};
+use crate::instances::InstanceBuffer;
+use crate::raster::CpuRenderer;
+use crate::state::State;
+use crate::{INDICES, VERTICES};
+use futures::executor::block_on;
+use sph::{Field, Simulation};
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use utils::{Instance, InstanceLayout};
+
+pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+// The particle view drawn into a texture and read back, without a window.
+pub struct Offscreen {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    sc_desc: wgpu::SwapChainDescriptor,
+    target: wgpu::Texture,
+    target_view: wgpu::TextureView,
+    // only held for its view
+    _depth_texture: wgpu::Texture,
+    depth_view: wgpu::TextureView,
+    readback: wgpu::Buffer,
+    // texture rows are copied COPY_BYTES_PER_ROW_ALIGNMENT aligned
+    padded_row: u32,
+    render_pipeline: wgpu::RenderPipeline,
+    sphere_pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    instance_buffer: InstanceBuffer,
+    camera_bind_group: wgpu::BindGroup,
+    camera2d_bind_group: wgpu::BindGroup,
+    color_buffer: wgpu::Buffer,
+    color_bind_group: wgpu::BindGroup,
+}
+
+impl Offscreen {
+    // None when there is no adapter. Nothing has to be presented, so any
+    // adapter will do, software ones included.
+    pub async fn new(width: u32, height: u32, instance_layout: InstanceLayout) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::all());
+        let adapter = match instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::Default,
+                compatible_surface: None,
+            })
+            .await
+        {
+            Some(adapter) => adapter,
+            None => instance.enumerate_adapters(wgpu::BackendBit::all()).next()?,
+        };
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    features: wgpu::Features::empty(),
+                    limits: wgpu::Limits::default(),
+                    shader_validation: true,
+                },
+                None,
+            )
+            .await
+            .ok()?;
+
+        // the pipeline builders only look at the format and size
+        let sc_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
+            format: OFFSCREEN_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: wgpu::Extent3d { width, height, depth: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let (depth_texture, depth_view) = State::depth_texture(&device, &sc_desc);
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = (width * 4 + align - 1) / align * align;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback"),
+            size: (padded_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let aspect = width as f32 / height as f32;
+        let camera_bind_group_layout = State::camera_bind_group_layout(&device);
+        let camera_buffer = State::camera_buffer(&device, &Camera::new(aspect));
+        let camera_bind_group = State::camera_bind_group(&device, &camera_bind_group_layout, &camera_buffer);
+        let camera2d_buffer = State::camera2d_buffer(&device, &Camera2d::new(aspect));
+        let camera2d_bind_group = State::camera_bind_group(&device, &camera_bind_group_layout, &camera2d_buffer);
+        let color_bind_group_layout = State::color_bind_group_layout(&device);
+        let color_buffer = State::color_buffer(&device, &ColorUniform::new(Colormap::Viridis, (0.0, 1.0)));
+        let color_bind_group = State::color_bind_group(&device, &color_bind_group_layout, &color_buffer);
+        let render_pipeline = State::render_pipeline(
+            &device,
+            &sc_desc,
+            &camera_bind_group_layout,
+            &color_bind_group_layout,
+            instance_layout,
+        );
+        let sphere_pipeline = State::sphere_pipeline(
+            &device,
+            &sc_desc,
+            &camera_bind_group_layout,
+            &color_bind_group_layout,
+            instance_layout,
+        );
+        let vertex_buffer = State::vertex_buffer(&device, VERTICES);
+        let index_buffer = State::index_buffer(&device, INDICES);
+        let instance_buffer = InstanceBuffer::new(&device, instance_layout, 0);
+        Some(Offscreen {
+            device,
+            queue,
+            sc_desc,
+            target,
+            target_view,
+            _depth_texture: depth_texture,
+            depth_view,
+            readback,
+            padded_row,
+            render_pipeline,
+            sphere_pipeline,
+            vertex_buffer,
+            index_buffer,
+            instance_buffer,
+            camera_bind_group,
+            camera2d_bind_group,
+            color_buffer,
+            color_bind_group,
+        })
+    }
+
+    // tightly packed sRGB RGBA8 rows, top to bottom, or the error from
+    // mapping the readback buffer
+    pub fn render(&mut self, instances: &[Instance], colors: ColorUniform, is_3d: bool) -> io::Result<Vec<u8>> {
+        let (width, height) = (self.sc_desc.width, self.sc_desc.height);
+        self.queue.write_buffer(&self.color_buffer, 0, bytemuck::cast_slice(&[colors]));
+        self.instance_buffer.write(&self.device, &self.queue, instances);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offscreen Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: &self.target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: if is_3d {
+                    Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                        attachment: &self.depth_view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    })
+                } else {
+                    None
+                },
+            });
+            if !instances.is_empty() {
+                if is_3d {
+                    render_pass.set_pipeline(&self.sphere_pipeline);
+                    render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+                } else {
+                    render_pass.set_pipeline(&self.render_pipeline);
+                    render_pass.set_bind_group(0, &self.camera2d_bind_group, &[]);
+                }
+                render_pass.set_bind_group(1, &self.color_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(self.index_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, self.instance_buffer.buffer.slice(..));
+                render_pass.draw_indexed(0..INDICES.len() as u32, 0, 0..instances.len() as u32);
+            }
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.target,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &self.readback,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: self.padded_row,
+                    rows_per_image: height,
+                },
+            },
+            wgpu::Extent3d { width, height, depth: 1 },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = self.readback.slice(..);
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        block_on(mapped).map_err(io::Error::other)?;
+        let mut image = Vec::with_capacity((width * height * 4) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_row as usize) {
+                image.extend_from_slice(&row[..(width * 4) as usize]);
+            }
+        }
+        self.readback.unmap();
+        Ok(image)
+    }
+}
+
+enum Backend {
+    Gpu(Box<Offscreen>),
+    Cpu(CpuRenderer),
+}
+
+// Writes every `interval`-th step of a run as a numbered PNG, drawn on the
+// GPU when there is an adapter and on the CPU otherwise.
+pub struct Recorder {
+    backend: Backend,
+    pub dir: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub interval: usize,
+    pub color_field: Field,
+    pub colormap: Colormap,
+    pub color_range: ColorRange,
+    // surface flags are only drawn with the full layout, on either backend
+    pub instance_layout: InstanceLayout,
+    steps: usize,
+    frames: usize,
+}
+
+impl Recorder {
+    pub async fn new(
+        dir: impl Into<PathBuf>,
+        width: u32,
+        height: u32,
+        interval: usize,
+        instance_layout: InstanceLayout,
+    ) -> Self {
+        let mut recorder = Recorder::cpu(dir, width, height, interval);
+        recorder.instance_layout = instance_layout;
+        if let Some(gpu) = Offscreen::new(width, height, instance_layout).await {
+            recorder.backend = Backend::Gpu(Box::new(gpu));
+        }
+        recorder
+    }
+    pub fn cpu(dir: impl Into<PathBuf>, width: u32, height: u32, interval: usize) -> Self {
+        Recorder {
+            backend: Backend::Cpu(CpuRenderer::new(width, height)),
+            dir: dir.into(),
+            width,
+            height,
+            interval: interval.max(1),
+            color_field: Field::Speed,
+            colormap: Colormap::Viridis,
+            color_range: ColorRange::Auto,
+            instance_layout: InstanceLayout::Full,
+            steps: 0,
+            frames: 0,
+        }
+    }
+    pub fn is_gpu(&self) -> bool {
+        matches!(self.backend, Backend::Gpu(_))
+    }
+    // call once per step, returns the path of the frame when one was due
+    pub fn record(&mut self, model: &dyn Simulation) -> io::Result<Option<PathBuf>> {
+        let due = self.steps % self.interval == 0;
+        self.steps += 1;
+        if !due {
+            return Ok(None);
+        }
+        let image = self.render(model)?;
+        let path = self.dir.join(format!("frame_{:05}.png", self.frames));
+        write_png(&path, self.width, self.height, &image)?;
+        self.frames += 1;
+        Ok(Some(path))
+    }
+    pub fn render(&mut self, model: &dyn Simulation) -> io::Result<Vec<u8>> {
+        let mut instances = model.instances();
+        let scalars = model.scalars(self.color_field);
+        for (instance, &scalar) in instances.iter_mut().zip(&scalars) {
+            instance.scalar = scalar;
+            instance.surface &= self.instance_layout == InstanceLayout::Full;
+        }
+        let range = self.color_range.resolve(&scalars);
+        let is_3d = model.is_3d();
+        let aspect = self.width as f32 / self.height as f32;
+        match &mut self.backend {
+            Backend::Gpu(gpu) => gpu.render(&instances, ColorUniform::new(self.colormap, range), is_3d),
+            Backend::Cpu(cpu) if is_3d => Ok(cpu.render_3d(&instances, &Camera::new(aspect), self.colormap, range)),
+            Backend::Cpu(cpu) => Ok(cpu.render_2d(&instances, &Camera2d::new(aspect), false, self.colormap, range)),
+        }
+    }
+}
+
+pub fn write_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(rgba).map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sph::Sph;
+
+    #[test]
+    fn numbered_frames_every_interval() {
+        let dir = std::env::temp_dir().join(format!("icr-frames-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut sph = Sph::new(4);
+        let mut recorder = Recorder::cpu(&dir, 32, 24, 3);
+        let mut written = vec![];
+        for _ in 0..7 {
+            sph.timestep().unwrap();
+            if let Some(path) = recorder.record(&sph).unwrap() {
+                written.push(path.file_name().unwrap().to_str().unwrap().to_string());
+            }
+        }
+        assert_eq!(written, ["frame_00000.png", "frame_00001.png", "frame_00002.png"]);
+
+        let decoder = png::Decoder::new(File::open(dir.join("frame_00002.png")).unwrap());
+        let (info, _) = decoder.read_info().unwrap();
+        assert_eq!((info.width, info.height), (32, 24));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
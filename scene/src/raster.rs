@@ -0,0 +1,250 @@
+use crate::camera::{Camera, Camera2d};
+use crate::colors::Colormap;
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4};
+use utils::Instance;
+
+const BACKGROUND: [f32; 3] = [1.0, 1.0, 1.0];
+
+// Pure CPU version of the particle view for machines without an adapter.
+// It follows the shaders: antialiased circles blended in linear color,
+// or sphere impostors with the same light and depth. Output rows run top
+// to bottom in sRGB RGBA8, like the offscreen texture.
+pub struct CpuRenderer {
+    pub width: u32,
+    pub height: u32,
+    color: Vec<[f32; 3]>,
+    depth: Vec<f32>,
+}
+
+impl CpuRenderer {
+    pub fn new(width: u32, height: u32) -> Self {
+        let n = (width * height) as usize;
+        CpuRenderer {
+            width,
+            height,
+            color: vec![BACKGROUND; n],
+            depth: vec![1.0; n],
+        }
+    }
+
+    // flat circles in instance order like shader.frag, with the rim on
+    // surface particles, or spheres sorted per pixel like shaded.frag
+    pub fn render_2d(
+        &mut self,
+        instances: &[Instance],
+        camera: &Camera2d,
+        shaded: bool,
+        colormap: Colormap,
+        range: (f32, f32),
+    ) -> Vec<u8> {
+        self.clear();
+        let view_proj = camera.view_proj();
+        // world units are square, one scale fits both axes
+        let pixels_per_unit = view_proj.x.x * 0.5 * self.width as f32;
+        for instance in instances {
+            let center = self.to_pixel(view_proj * instance.position.extend(1.0));
+            let radius = instance.scale.x * pixels_per_unit;
+            let color = colormap.color(instance.scalar, range);
+            let size = (self.width, self.height);
+            cover(size, center, radius, radius, |i, uv| {
+                let d = uv.magnitude();
+                if shaded {
+                    let r2 = d * d;
+                    if r2 > 1.0 {
+                        return;
+                    }
+                    let normal = uv.extend((1.0 - r2).sqrt());
+                    self.shade(i, 0.5 * (1.0 - normal.z), normal, color);
+                } else {
+                    // fwidth of the distance over one pixel step in x and y
+                    let fwidth = if d > 0.0 { (uv.x.abs() + uv.y.abs()) / (d * radius) } else { 1.0 / radius };
+                    let alpha = 1.0 - smoothstep(1.0 - fwidth, 1.0, d);
+                    if alpha > 0.0 {
+                        let outward = if d > 0.0 { (uv / d).dot(instance.normal).max(0.0) } else { 0.0 };
+                        let rim = if instance.surface { outward * smoothstep(0.6, 0.8, d) } else { 0.0 };
+                        let shade = 1.0 - 0.6 * rim;
+                        let dst = self.color[i];
+                        self.color[i] = [0, 1, 2].map(|c| color[c] * shade * alpha + dst[c] * (1.0 - alpha));
+                    }
+                }
+            });
+        }
+        self.encode()
+    }
+
+    // sphere impostors as in sphere.vert and sphere.frag
+    pub fn render_3d(&mut self, instances: &[Instance], camera: &Camera, colormap: Colormap, range: (f32, f32)) -> Vec<u8> {
+        self.clear();
+        let (view, proj) = (camera.view(), camera.projection());
+        let (width, height) = (self.width as f32, self.height as f32);
+        for instance in instances {
+            let center = view * instance.position.extend(1.0);
+            if center.z >= 0.0 {
+                continue;
+            }
+            let radius = instance.scale.x;
+            let color = colormap.color(instance.scalar, range);
+            // camera facing square of the quad, in pixels
+            let scale = Vector2::new(proj.x.x * 0.5 * width, proj.y.y * 0.5 * height) / -center.z;
+            let pixel = self.to_pixel(proj * center);
+            cover((self.width, self.height), pixel, radius * scale.x, radius * scale.y, |i, uv| {
+                let r2 = uv.magnitude2();
+                if r2 > 1.0 {
+                    return;
+                }
+                let normal = uv.extend((1.0 - r2).sqrt());
+                let clip = proj * (center.truncate() + normal * radius).extend(1.0);
+                self.shade(i, clip.z / clip.w, normal, color);
+            });
+        }
+        self.encode()
+    }
+
+    fn clear(&mut self) {
+        self.color.iter_mut().for_each(|c| *c = BACKGROUND);
+        self.depth.iter_mut().for_each(|d| *d = 1.0);
+    }
+
+    fn to_pixel(&self, clip: Vector4<f32>) -> Vector2<f32> {
+        let ndc = clip.truncate() / clip.w;
+        Vector2::new((ndc.x + 1.0) * 0.5 * self.width as f32, (1.0 - ndc.y) * 0.5 * self.height as f32)
+    }
+
+    // depth tested with Less, opaque
+    fn shade(&mut self, i: usize, depth: f32, normal: Vector3<f32>, color: [f32; 3]) {
+        if depth >= self.depth[i] {
+            return;
+        }
+        self.depth[i] = depth;
+        let light = Vector3::new(0.4, 0.6, 1.0).normalize();
+        let diffuse = normal.dot(light).max(0.0);
+        self.color[i] = color.map(|c| c * (0.25 + 0.75 * diffuse));
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        self.color.iter().flat_map(|c| [srgb(c[0]), srgb(c[1]), srgb(c[2]), 255]).collect()
+    }
+}
+
+// calls `f` with the pixel index and quad coordinates, y up and 1 at
+// the edges, for every pixel center inside the quad
+fn cover<F: FnMut(usize, Vector2<f32>)>((width, height): (u32, u32), center: Vector2<f32>, rx: f32, ry: f32, mut f: F) {
+    if !(rx > 0.0 && ry > 0.0) {
+        return;
+    }
+    let x0 = (center.x - rx - 0.5).ceil().max(0.0) as u32;
+    let y0 = (center.y - ry - 0.5).ceil().max(0.0) as u32;
+    let x1 = ((center.x + rx - 0.5).floor() + 1.0).max(0.0).min(width as f32) as u32;
+    let y1 = ((center.y + ry - 0.5).floor() + 1.0).max(0.0).min(height as f32) as u32;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let uv = Vector2::new((x as f32 + 0.5 - center.x) / rx, (center.y - y as f32 - 0.5) / ry);
+            f((y * width + x) as usize, uv);
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// linear to sRGB encoded, as the Srgb texture formats store it
+pub fn srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let s = if c <= 0.003_130_8 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (s * 255.0 + 0.5) as u8
+}
+
+// largest difference in any channel, for comparing against golden images
+pub fn max_difference(a: &[u8], b: &[u8]) -> u8 {
+    assert_eq!(a.len(), b.len());
+    a.iter().zip(b).map(|(x, y)| x.max(y) - x.min(y)).max().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Quaternion;
+    use std::fs::File;
+    use std::path::PathBuf;
+
+    fn instance(x: f32, y: f32, z: f32, radius: f32, scalar: f32) -> Instance {
+        Instance {
+            position: Vector3::new(x, y, z),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector2::new(radius, radius),
+            surface: false,
+            normal: Vector2::new(0.0, 0.0),
+            scalar,
+        }
+    }
+
+    fn scene() -> Vec<Instance> {
+        vec![
+            instance(-0.3, -0.2, 0.0, 0.35, 0.0),
+            instance(0.1, 0.0, -0.2, 0.35, 0.5),
+            instance(0.4, 0.3, 0.2, 0.25, 1.0),
+        ]
+    }
+
+    // Compares with scene/golden/<name>.png, UPDATE_GOLDEN=1 rewrites it.
+    // A couple of levels of slack absorb float differences between
+    // platforms.
+    fn check_golden(name: &str, (width, height): (u32, u32), image: &[u8]) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden").join(format!("{}.png", name));
+        if std::env::var("UPDATE_GOLDEN").is_ok() {
+            crate::offscreen::write_png(&path, width, height, image).unwrap();
+            return;
+        }
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let (info, mut reader) = decoder.read_info().unwrap();
+        assert_eq!((info.width, info.height), (width, height));
+        let mut golden = vec![0; info.buffer_size()];
+        reader.next_frame(&mut golden).unwrap();
+        assert!(max_difference(image, &golden) <= 2, "{} differs from its golden image", name);
+    }
+
+    #[test]
+    fn flat_circles() {
+        let mut renderer = CpuRenderer::new(64, 48);
+        let camera = Camera2d::new(64.0 / 48.0);
+        let image = renderer.render_2d(&scene(), &camera, false, Colormap::Jet, (0.0, 1.0));
+        // the last instance is drawn on top
+        let center = (((48 / 2 - 7) * 64 + 32 + 10) * 4) as usize;
+        assert_eq!(&image[center..center + 4], &[srgb(0.5), 0, 0, 255]);
+        assert_eq!(&image[0..4], &[255, 255, 255, 255]);
+        check_golden("flat_2d", (64, 48), &image);
+    }
+
+    #[test]
+    fn surface_rim_faces_out() {
+        let mut renderer = CpuRenderer::new(64, 48);
+        let camera = Camera2d::new(64.0 / 48.0);
+        let mut surface = instance(0.0, 0.0, 0.0, 0.5, 0.0);
+        surface.surface = true;
+        surface.normal = Vector2::new(0.0, 1.0);
+        let image = renderer.render_2d(&[surface], &camera, false, Colormap::Jet, (0.0, 1.0));
+        // pixel centers at 0.7 of the 12 pixel radius above and below the center
+        let above = (((48 / 2 - 9) * 64 + 32) * 4) as usize;
+        let below = (((48 / 2 + 8) * 64 + 32) * 4) as usize;
+        assert!(image[above + 2] < image[below + 2], "{:?} {:?}", &image[above..above + 4], &image[below..below + 4]);
+        check_golden("surface_2d", (64, 48), &image);
+    }
+
+    #[test]
+    fn shaded_spheres() {
+        let mut renderer = CpuRenderer::new(64, 48);
+        let camera = Camera2d::new(64.0 / 48.0);
+        let image = renderer.render_2d(&scene(), &camera, true, Colormap::Viridis, (0.0, 1.0));
+        check_golden("shaded_2d", (64, 48), &image);
+    }
+
+    #[test]
+    fn spheres_3d() {
+        let mut renderer = CpuRenderer::new(64, 48);
+        let camera = Camera::new(64.0 / 48.0);
+        let image = renderer.render_3d(&scene(), &camera, Colormap::Coolwarm, (0.0, 1.0));
+        check_golden("spheres_3d", (64, 48), &image);
+    }
+}
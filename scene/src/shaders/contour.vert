@@ -0,0 +1,11 @@
+#version 450
+
+layout(location=0) in vec2 a_position;
+
+layout(set=0, binding=0) uniform Camera2d {
+    mat4 u_view_proj;
+};
+
+void main() {
+    gl_Position = u_view_proj * vec4(a_position, 0.0, 1.0);
+}
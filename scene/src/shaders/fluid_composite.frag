@@ -0,0 +1,57 @@
+#version 450
+
+layout(set=0, binding=0) uniform Fluid {
+    float u_radius;
+    uint u_perspective;
+};
+layout(set=0, binding=1) uniform texture2D t_position;
+layout(set=0, binding=2) uniform sampler s_fluid;
+layout(set=0, binding=3) uniform texture2D t_thickness;
+
+layout(location=0) out vec4 f_color;
+
+vec4 fetch(ivec2 pixel) {
+    ivec2 size = textureSize(sampler2D(t_position, s_fluid), 0);
+    return texelFetch(sampler2D(t_position, s_fluid), clamp(pixel, ivec2(0), size - 1), 0);
+}
+
+// one sided difference along `step`, from the side nearer in depth so the
+// surface edge does not pick up the background or another layer
+vec3 derivative(ivec2 pixel, ivec2 step, vec3 center) {
+    vec4 ahead = fetch(pixel + step);
+    vec4 behind = fetch(pixel - step);
+    vec3 forward = ahead.xyz - center;
+    vec3 backward = center - behind.xyz;
+    if (ahead.w == 0.0) {
+        return backward;
+    }
+    if (behind.w == 0.0 || abs(forward.z) < abs(backward.z)) {
+        return forward;
+    }
+    return backward;
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    vec4 p = fetch(pixel);
+    if (p.w == 0.0) {
+        discard;
+    }
+    // screen y grows downwards, the normal faces the camera
+    vec3 ddx = derivative(pixel, ivec2(1, 0), p.xyz);
+    vec3 ddy = derivative(pixel, ivec2(0, 1), p.xyz);
+    vec3 normal = normalize(cross(ddy, ddx));
+
+    vec3 view = u_perspective == 1u ? -normalize(p.xyz) : vec3(0.0, 0.0, 1.0);
+    vec3 light = normalize(vec3(0.4, 0.6, 1.0));
+    float diffuse = max(dot(normal, light), 0.0);
+    float specular = pow(max(dot(normal, normalize(light + view)), 0.0), 60.0);
+    float fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, view), 0.0), 5.0);
+
+    // Beer-Lambert absorption of the white background, thickness is
+    // measured in particle diameters
+    float thickness = texelFetch(sampler2D(t_thickness, s_fluid), pixel, 0).r;
+    vec3 transmitted = exp(-vec3(0.6, 0.2, 0.05) * thickness / (2.0 * u_radius));
+    vec3 color = transmitted * (0.7 + 0.3 * diffuse);
+    f_color = vec4(mix(color, vec3(1.0), fresnel) + specular, 1.0);
+}
@@ -0,0 +1,25 @@
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=1) in vec3 v_center;
+layout(location=2) in float v_radius;
+
+layout(set=0, binding=0) uniform Camera {
+    mat4 u_view;
+    mat4 u_proj;
+};
+
+layout(location=0) out vec4 f_position;
+
+void main() {
+    float r2 = dot(v_uv, v_uv);
+    if (r2 > 1.0) {
+        discard;
+    }
+    // nearest sphere surface in view space, w marks covered pixels
+    vec3 normal = vec3(v_uv, sqrt(1.0 - r2));
+    vec3 position = v_center + normal * v_radius;
+    vec4 clip = u_proj * vec4(position, 1.0);
+    gl_FragDepth = clip.z / clip.w;
+    f_position = vec4(position, 1.0);
+}
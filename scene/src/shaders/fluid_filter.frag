@@ -0,0 +1,45 @@
+#version 450
+
+layout(set=0, binding=0) uniform Fluid {
+    float u_radius;
+    uint u_perspective;
+};
+layout(set=0, binding=1) uniform texture2D t_position;
+layout(set=0, binding=2) uniform sampler s_fluid;
+
+layout(location=0) out vec4 f_position;
+
+const int KERNEL = 6;
+
+vec4 fetch(ivec2 pixel) {
+    ivec2 size = textureSize(sampler2D(t_position, s_fluid), 0);
+    return texelFetch(sampler2D(t_position, s_fluid), clamp(pixel, ivec2(0), size - 1), 0);
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    vec4 center = fetch(pixel);
+    if (center.w == 0.0) {
+        discard;
+    }
+    // bilateral filter on depth, pixels count less the further their depth
+    // is from this one so that separate layers are not blurred together
+    float sum = 0.0;
+    float weight = 0.0;
+    for (int y = -KERNEL; y <= KERNEL; y++) {
+        for (int x = -KERNEL; x <= KERNEL; x++) {
+            vec4 p = fetch(pixel + ivec2(x, y));
+            if (p.w == 0.0) {
+                continue;
+            }
+            float dz = (p.z - center.z) / u_radius;
+            float w = exp(-float(x * x + y * y) / float(KERNEL * KERNEL) - dz * dz);
+            sum += w * p.z;
+            weight += w;
+        }
+    }
+    float z = sum / weight;
+    // the pixel's view ray is fixed, a perspective one scales with depth
+    vec2 xy = u_perspective == 1u ? center.xy * (z / center.z) : center.xy;
+    f_position = vec4(xy, z, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=1) in vec3 v_center;
+layout(location=2) in float v_radius;
+
+layout(location=0) out float f_thickness;
+
+void main() {
+    float r2 = dot(v_uv, v_uv);
+    if (r2 > 1.0) {
+        discard;
+    }
+    // chord through the sphere, added up over every particle on the pixel
+    f_thickness = 2.0 * sqrt(1.0 - r2) * v_radius;
+}
@@ -0,0 +1,7 @@
+#version 450
+
+void main() {
+    // one triangle covering the whole screen
+    vec2 p = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(p * 2.0 - 1.0, 0.0, 1.0);
+}
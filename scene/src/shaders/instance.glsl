@@ -0,0 +1,55 @@
+// Per instance inputs in either layout, COMPACT selects InstanceCompact.
+
+#ifdef COMPACT
+layout(location=5) in vec3 i_position;
+layout(location=6) in float i_radius;
+layout(location=7) in float i_scalar;
+
+// point of the particle quad at `offset` from its center, in world space
+vec4 instance_point(vec2 offset) {
+    return vec4(i_position + vec3(offset * i_radius, 0.0), 1.0);
+}
+
+float instance_radius() {
+    return i_radius;
+}
+
+float instance_scalar() {
+    return i_scalar;
+}
+
+// the compact layout has no surface flags
+float instance_surface() {
+    return 0.0;
+}
+
+vec2 instance_normal() {
+    return vec2(0.0);
+}
+#else
+layout(location=5) in mat4 model_matrix;
+layout(location=9) in vec2 scale;
+layout(location=10) in vec2 normal;
+layout(location=11) in float surface;
+layout(location=12) in float scalar;
+
+vec4 instance_point(vec2 offset) {
+    return model_matrix * vec4(offset * scale, 0.0, 1.0);
+}
+
+float instance_radius() {
+    return scale.x;
+}
+
+float instance_scalar() {
+    return scalar;
+}
+
+float instance_surface() {
+    return surface;
+}
+
+vec2 instance_normal() {
+    return normal;
+}
+#endif
@@ -0,0 +1,20 @@
+#include "instance.glsl"
+
+layout(location=0) in vec2 a_position;
+
+layout(set=0, binding=0) uniform Camera2d {
+    mat4 u_view_proj;
+};
+
+layout(location=0) out float v_scalar;
+layout(location=1) out vec2 v_uv;
+layout(location=2) out float v_surface;
+layout(location=3) out vec2 v_normal;
+
+void main() {
+    v_scalar = instance_scalar();
+    v_uv = a_position;
+    v_surface = instance_surface();
+    v_normal = instance_normal();
+    gl_Position = u_view_proj * instance_point(a_position);
+}
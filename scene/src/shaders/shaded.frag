@@ -0,0 +1,28 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "colormap.glsl"
+
+layout(location=0) in float v_scalar;
+layout(location=1) in vec2 v_uv;
+
+layout(set=1, binding=0) uniform Colors {
+    vec2 u_range;
+    uint u_colormap;
+};
+
+layout(location=0) out vec4 f_color;
+
+void main() {
+    float r2 = dot(v_uv, v_uv);
+    if (r2 > 1.0) {
+        discard;
+    }
+    // every sphere sits on the z = 0 plane, the center is nearest
+    vec3 normal = vec3(v_uv, sqrt(1.0 - r2));
+    gl_FragDepth = 0.5 * (1.0 - normal.z);
+
+    vec3 light = normalize(vec3(0.4, 0.6, 1.0));
+    float diffuse = max(dot(normal, light), 0.0);
+    f_color = vec4(colormap(v_scalar, u_range, u_colormap) * (0.25 + 0.75 * diffuse), 1.0);
+}
@@ -1,7 +1,30 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "colormap.glsl"
+
+layout(location=0) in float v_scalar;
+layout(location=1) in vec2 v_uv;
+layout(location=2) in float v_surface;
+layout(location=3) in vec2 v_normal;
+
+layout(set=1, binding=0) uniform Colors {
+    vec2 u_range;
+    uint u_colormap;
+};
 
 layout(location=0) out vec4 f_color;
 
 void main() {
-    f_color = vec4(0.0, 0.64, 1.0, 1.0);
+    // unit circle inside the quad, antialiased over one pixel of the edge
+    float d = length(v_uv);
+    float alpha = 1.0 - smoothstep(1.0 - fwidth(d), 1.0, d);
+    if (alpha <= 0.0) {
+        discard;
+    }
+    // free surface particles get a dark rim on the side facing out of
+    // the fluid, the 2D view is not rotated so the normal is in quad axes
+    float outward = d > 0.0 ? max(dot(v_uv / d, v_normal), 0.0) : 0.0;
+    float rim = v_surface * outward * smoothstep(0.6, 0.8, d);
+    f_color = vec4(colormap(v_scalar, u_range, u_colormap) * (1.0 - 0.6 * rim), alpha);
 }
@@ -0,0 +1,36 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+#include "colormap.glsl"
+
+layout(location=0) in vec2 v_uv;
+layout(location=1) in vec3 v_center;
+layout(location=2) in float v_radius;
+layout(location=3) in float v_scalar;
+
+layout(set=0, binding=0) uniform Camera {
+    mat4 u_view;
+    mat4 u_proj;
+};
+
+layout(set=1, binding=0) uniform Colors {
+    vec2 u_range;
+    uint u_colormap;
+};
+
+layout(location=0) out vec4 f_color;
+
+void main() {
+    float r2 = dot(v_uv, v_uv);
+    if (r2 > 1.0) {
+        discard;
+    }
+    // view space normal and surface point of the sphere under this pixel
+    vec3 normal = vec3(v_uv, sqrt(1.0 - r2));
+    vec4 clip = u_proj * vec4(v_center + normal * v_radius, 1.0);
+    gl_FragDepth = clip.z / clip.w;
+
+    vec3 light = normalize(vec3(0.4, 0.6, 1.0));
+    float diffuse = max(dot(normal, light), 0.0);
+    f_color = vec4(colormap(v_scalar, u_range, u_colormap) * (0.25 + 0.75 * diffuse), 1.0);
+}
@@ -0,0 +1,24 @@
+#include "instance.glsl"
+
+layout(location=0) in vec2 a_position;
+
+layout(set=0, binding=0) uniform Camera {
+    mat4 u_view;
+    mat4 u_proj;
+};
+
+layout(location=0) out vec2 v_uv;
+layout(location=1) out vec3 v_center;
+layout(location=2) out float v_radius;
+layout(location=3) out float v_scalar;
+
+void main() {
+    vec4 center = u_view * instance_point(vec2(0.0));
+    float radius = instance_radius();
+    v_uv = a_position;
+    v_center = center.xyz;
+    v_radius = radius;
+    v_scalar = instance_scalar();
+    // camera facing quad around the sphere center
+    gl_Position = u_proj * (center + vec4(a_position * radius, 0.0, 0.0));
+}
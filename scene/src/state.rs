@@ -1,10 +1,61 @@
-use sph::Sph;
+use crate::camera::{Camera, Camera2d, Camera2dUniform, CameraUniform};
+use crate::colors::{ColorRange, ColorUniform, Colormap};
+use crate::error::SetupError;
+use crate::fluid::FluidRenderer;
+use crate::instances::InstanceBuffer;
+use sph::{Field, Simulation};
 use utils::Instance;
-use utils::InstanceRaw;
+use utils::InstanceLayout;
 use utils::Vertex;
 use wgpu::util::DeviceExt;
 use winit::window::Window;
 
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// What a frame of the particle view draws with. The pipelines take their
+// depth state from here and the pass attaches the depth texture from
+// here, wgpu rejects a pipeline whose depth state does not match the pass.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Draw {
+    Nothing,
+    Contour,
+    Flat,
+    Shaded,
+    Spheres,
+}
+
+impl Draw {
+    // the contour replaces the particles, and only exists in 2D
+    pub fn choose(is_3d: bool, shaded: bool, has_mesh: bool, has_instances: bool) -> Draw {
+        if has_mesh && !is_3d {
+            Draw::Contour
+        } else if !has_instances {
+            Draw::Nothing
+        } else if is_3d {
+            Draw::Spheres
+        } else if shaded {
+            Draw::Shaded
+        } else {
+            Draw::Flat
+        }
+    }
+    pub fn depth(self) -> bool {
+        matches!(self, Draw::Shaded | Draw::Spheres)
+    }
+    // opaque, depth tested with Less
+    fn depth_stencil_state(self) -> Option<wgpu::DepthStencilStateDescriptor> {
+        if !self.depth() {
+            return None;
+        }
+        Some(wgpu::DepthStencilStateDescriptor {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilStateDescriptor::default(),
+        })
+    }
+}
+
 pub struct State {
     pub surface: wgpu::Surface,
     pub device: wgpu::Device,
@@ -13,44 +64,89 @@ pub struct State {
     pub sc_desc: wgpu::SwapChainDescriptor,
     pub swap_chain: wgpu::SwapChain,
     pub render_pipeline: wgpu::RenderPipeline,
+    pub shaded_pipeline: wgpu::RenderPipeline,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
+    pub sphere_pipeline: wgpu::RenderPipeline,
+    pub camera: Camera,
+    pub camera_buffer: wgpu::Buffer,
+    pub camera_bind_group: wgpu::BindGroup,
+    pub camera2d: Camera2d,
+    pub camera2d_buffer: wgpu::Buffer,
+    pub camera2d_bind_group: wgpu::BindGroup,
+    // last cursor position, and whether the left button is dragging the view
+    pub cursor: Option<winit::dpi::PhysicalPosition<f64>>,
+    pub dragging: bool,
+    pub depth_texture: wgpu::Texture,
+    pub depth_view: wgpu::TextureView,
+    pub color_field: Field,
+    pub colormap: Colormap,
+    pub color_range: ColorRange,
+    pub color_buffer: wgpu::Buffer,
+    pub color_bind_group: wgpu::BindGroup,
     pub instances: Vec<Instance>,
-    pub instance_buffer: wgpu::Buffer,
-    pub model: Sph,
+    pub instance_buffer: InstanceBuffer,
+    pub model: Box<dyn Simulation>,
+    pub running: bool,
+    // 2D particles drawn as shaded spheres instead of flat circles
+    pub shaded: bool,
+    // screen-space fluid surface instead of particles
+    pub fluid: bool,
+    pub fluid_renderer: FluidRenderer,
+    // filled marching squares outline instead of particles, 2D only
+    pub contour: bool,
+    pub contour_pipeline: wgpu::RenderPipeline,
 }
 
 impl State {
-    pub fn instance() -> wgpu::Instance {
-        wgpu::Instance::new(wgpu::BackendBit::PRIMARY)
+    pub fn instance(backends: wgpu::BackendBit) -> wgpu::Instance {
+        wgpu::Instance::new(backends)
     }
     pub fn surface(instance: &wgpu::Instance, window: &Window) -> wgpu::Surface {
         unsafe { instance.create_surface(window) }
     }
+    // The primary backends first and then DX11 and GL, together all of
+    // them, each time the default adapter and then a low power one.
     pub async fn device_queue(
-        instance: &wgpu::Instance,
-        surface: &wgpu::Surface,
-    ) -> (wgpu::Device, wgpu::Queue) {
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::Default,
-                compatible_surface: Some(surface),
-            })
-            .await
-            .unwrap();
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                    shader_validation: true,
-                },
-                None,
-            )
-            .await
-            .unwrap();
-        (device, queue)
+        window: &Window,
+    ) -> Result<(wgpu::Surface, wgpu::Device, wgpu::Queue), SetupError> {
+        let mut error = SetupError {
+            tried: vec![],
+            refused: vec![],
+        };
+        for &backends in &[wgpu::BackendBit::PRIMARY, wgpu::BackendBit::SECONDARY] {
+            let instance = State::instance(backends);
+            let surface = State::surface(&instance, window);
+            for &power_preference in &[wgpu::PowerPreference::Default, wgpu::PowerPreference::LowPower] {
+                let adapter = match instance
+                    .request_adapter(&wgpu::RequestAdapterOptions {
+                        power_preference,
+                        compatible_surface: Some(&surface),
+                    })
+                    .await
+                {
+                    Some(adapter) => adapter,
+                    None => continue,
+                };
+                let device = adapter
+                    .request_device(
+                        &wgpu::DeviceDescriptor {
+                            features: wgpu::Features::empty(),
+                            limits: wgpu::Limits::default(),
+                            shader_validation: true,
+                        },
+                        None,
+                    )
+                    .await;
+                match device {
+                    Ok((device, queue)) => return Ok((surface, device, queue)),
+                    Err(_) => error.refused.push(adapter.get_info().name),
+                }
+            }
+            error.tried.push(backends);
+        }
+        Err(error)
     }
     pub fn size(window: &Window) -> winit::dpi::PhysicalSize<u32> {
         window.inner_size()
@@ -71,29 +167,48 @@ impl State {
     ) -> wgpu::SwapChain {
         device.create_swap_chain(surface, sc_desc)
     }
-    pub fn render_pipeline(
+    // vertex shader of the 2D particle quads in the chosen instance layout
+    fn particle_vs(device: &wgpu::Device, instance_layout: InstanceLayout) -> wgpu::ShaderModule {
+        match instance_layout {
+            InstanceLayout::Full => device.create_shader_module(wgpu::include_spirv!("./shaders/shader.vert.spv")),
+            InstanceLayout::Compact => {
+                device.create_shader_module(wgpu::include_spirv!("./shaders/shader_compact.vert.spv"))
+            }
+        }
+    }
+    fn opaque(format: wgpu::TextureFormat) -> wgpu::ColorStateDescriptor {
+        wgpu::ColorStateDescriptor {
+            format,
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }
+    }
+    // The particle, sphere and contour pipelines only differ in these
+    // parameters, everything else is the same triangle list setup.
+    fn pipeline(
         device: &wgpu::Device,
-        sc_desc: &wgpu::SwapChainDescriptor,
+        label: &str,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        shaders: (&wgpu::ShaderModule, &wgpu::ShaderModule),
+        color_state: wgpu::ColorStateDescriptor,
+        depth_stencil_state: Option<wgpu::DepthStencilStateDescriptor>,
+        vertex_buffers: &[wgpu::VertexBufferDescriptor],
     ) -> wgpu::RenderPipeline {
-        let vs_module =
-            device.create_shader_module(wgpu::include_spirv!("./shaders/shader.vert.spv"));
-        let fs_module =
-            device.create_shader_module(wgpu::include_spirv!("./shaders/shader.frag.spv"));
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Reder Pipeline Layout"),
-                bind_group_layouts: &[],
-                push_constant_ranges: &[],
-            });
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
             vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module,
+                module: shaders.0,
                 entry_point: "main",
             },
             fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module,
+                module: shaders.1,
                 entry_point: "main",
             }),
             rasterization_state: Some(wgpu::RasterizationStateDescriptor {
@@ -105,22 +220,215 @@ impl State {
                 clamp_depth: false,
             }),
             primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: sc_desc.format,
-                color_blend: wgpu::BlendDescriptor::REPLACE,
-                alpha_blend: wgpu::BlendDescriptor::REPLACE,
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            depth_stencil_state: None,
+            color_states: &[color_state],
+            depth_stencil_state,
             vertex_state: wgpu::VertexStateDescriptor {
                 index_format: wgpu::IndexFormat::Uint16,
-                vertex_buffers: &[Vertex::desc(), InstanceRaw::desc()],
+                vertex_buffers,
             },
             sample_count: 1,
             sample_mask: !0,
             alpha_to_coverage_enabled: false,
+        })
+    }
+    pub fn render_pipeline(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_bind_group_layout: &wgpu::BindGroupLayout,
+        instance_layout: InstanceLayout,
+    ) -> wgpu::RenderPipeline {
+        let vs_module = State::particle_vs(device, instance_layout);
+        let fs_module =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/shader.frag.spv"));
+        // the circles fade out over their last pixel
+        let color_state = wgpu::ColorStateDescriptor {
+            format: sc_desc.format,
+            color_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha_blend: wgpu::BlendDescriptor {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            write_mask: wgpu::ColorWrite::ALL,
+        };
+        State::pipeline(
+            device,
+            "Render Pipeline",
+            &[camera_bind_group_layout, color_bind_group_layout],
+            (&vs_module, &fs_module),
+            color_state,
+            Draw::Flat.depth_stencil_state(),
+            &[Vertex::desc(), instance_layout.desc()],
+        )
+    }
+    // 2D particles as sphere impostors, overlapping particles are sorted
+    // per pixel by the depth of their bulge
+    pub fn shaded_pipeline(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_bind_group_layout: &wgpu::BindGroupLayout,
+        instance_layout: InstanceLayout,
+    ) -> wgpu::RenderPipeline {
+        let vs_module = State::particle_vs(device, instance_layout);
+        let fs_module =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/shaded.frag.spv"));
+        State::pipeline(
+            device,
+            "Shaded Pipeline",
+            &[camera_bind_group_layout, color_bind_group_layout],
+            (&vs_module, &fs_module),
+            State::opaque(sc_desc.format),
+            Draw::Shaded.depth_stencil_state(),
+            &[Vertex::desc(), instance_layout.desc()],
+        )
+    }
+    // marching squares fill of the 2D fluid, one flat color
+    pub fn contour_pipeline(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let vs_module =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/contour.vert.spv"));
+        let fs_module =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/contour.frag.spv"));
+        State::pipeline(
+            device,
+            "Contour Pipeline",
+            &[camera_bind_group_layout],
+            (&vs_module, &fs_module),
+            State::opaque(sc_desc.format),
+            Draw::Contour.depth_stencil_state(),
+            &[Vertex::desc()],
+        )
+    }
+    pub fn sphere_pipeline(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_bind_group_layout: &wgpu::BindGroupLayout,
+        instance_layout: InstanceLayout,
+    ) -> wgpu::RenderPipeline {
+        let vs_module = match instance_layout {
+            InstanceLayout::Full => device.create_shader_module(wgpu::include_spirv!("./shaders/sphere.vert.spv")),
+            InstanceLayout::Compact => {
+                device.create_shader_module(wgpu::include_spirv!("./shaders/sphere_compact.vert.spv"))
+            }
+        };
+        let fs_module =
+            device.create_shader_module(wgpu::include_spirv!("./shaders/sphere.frag.spv"));
+        State::pipeline(
+            device,
+            "Sphere Pipeline",
+            &[camera_bind_group_layout, color_bind_group_layout],
+            (&vs_module, &fs_module),
+            State::opaque(sc_desc.format),
+            Draw::Spheres.depth_stencil_state(),
+            &[Vertex::desc(), instance_layout.desc()],
+        )
+    }
+    pub fn camera_buffer(device: &wgpu::Device, camera: &Camera) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new(camera)]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        })
+    }
+    pub fn camera2d_buffer(device: &wgpu::Device, camera: &Camera2d) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera 2D Buffer"),
+            contents: bytemuck::cast_slice(&[Camera2dUniform::new(camera)]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        })
+    }
+    pub fn camera_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Camera Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+    pub fn camera_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        camera_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(camera_buffer.slice(..)),
+            }],
+        })
+    }
+    pub fn color_buffer(device: &wgpu::Device, colors: &ColorUniform) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Color Buffer"),
+            contents: bytemuck::cast_slice(&[*colors]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        })
+    }
+    pub fn color_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Color Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        })
+    }
+    pub fn color_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        color_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Color Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(color_buffer.slice(..)),
+            }],
+        })
+    }
+    pub fn depth_texture(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth Texture"),
+            size: wgpu::Extent3d {
+                width: sc_desc.width,
+                height: sc_desc.height,
+                depth: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
         });
-        render_pipeline
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
     }
     pub fn vertex_buffer(device: &wgpu::Device, vertices: &[Vertex]) -> wgpu::Buffer {
         device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -139,13 +447,23 @@ impl State {
     pub fn num_indices(indices: &[u16]) -> u32 {
         indices.len() as u32
     }
-    pub fn instance_buffer(device: &wgpu::Device, instances: &Vec<Instance>) -> wgpu::Buffer {
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsage::VERTEX,
-        });
-        instance_buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_only_with_depth_tested_pipelines() {
+        // contour and shaded spheres both on in 2D draw the contour alone,
+        // whose pipeline has no depth state
+        assert_eq!(Draw::choose(false, true, true, true), Draw::Contour);
+        assert!(!Draw::Contour.depth());
+        assert_eq!(Draw::choose(false, true, false, true), Draw::Shaded);
+        assert_eq!(Draw::choose(true, false, true, true), Draw::Spheres);
+        assert_eq!(Draw::choose(false, true, false, false), Draw::Nothing);
+        for &draw in &[Draw::Nothing, Draw::Contour, Draw::Flat, Draw::Shaded, Draw::Spheres] {
+            assert_eq!(draw.depth(), draw.depth_stencil_state().is_some(), "{:?}", draw);
+        }
     }
 }
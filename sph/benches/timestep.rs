@@ -0,0 +1,268 @@
+use cgmath::{InnerSpace, Vector2};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rand::seq::SliceRandom;
+use sph::{Domain, Real, Sph};
+
+const STEPS: usize = 10;
+
+// every batch starts from a fresh dam break so all samples simulate the
+// same thing, the 2x2 box holds the largest block
+fn timestep(c: &mut Criterion) {
+    let mut group = c.benchmark_group("timestep");
+    group.sample_size(10);
+    for &rows in &[20, 40, 60] {
+        group.bench_with_input(BenchmarkId::from_parameter(rows * rows), &rows, |b, &rows| {
+            b.iter_batched(
+                || {
+                    let mut sph = Sph::new(rows);
+                    sph.params.domain = Domain { width: 2.0, height: 2.0, ..Domain::default() };
+                    sph
+                },
+                |mut sph| {
+                    for _ in 0..STEPS {
+                        sph.timestep().unwrap();
+                    }
+                    sph
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+// density pass alone over a settled block, the neighbor lists are built
+// once outside the loop
+fn density(c: &mut Criterion) {
+    let mut sph = Sph::new(60);
+    sph.params.domain = Domain { width: 2.0, height: 2.0, ..Domain::default() };
+    for _ in 0..STEPS {
+        sph.timestep().unwrap();
+    }
+    sph.find_neighbors();
+    let n = sph.particles.len();
+    c.bench_function("density_3600", |b| {
+        b.iter(|| {
+            for p in 0..n {
+                sph.density(p);
+            }
+        })
+    });
+}
+
+// 50k particle dam break in an 8x8 box. Shuffled storage stands in for a
+// flow that has mixed for a while.
+fn dam_break(shuffle: bool, reorder_every: Option<usize>) -> Sph {
+    let mut sph = Sph::new(224);
+    sph.params.domain = Domain { width: 8.0, height: 8.0, ..Domain::default() };
+    sph.params.reorder_every = reorder_every;
+    if shuffle {
+        let mut particles: Vec<_> = sph.particles.iter().collect();
+        particles.shuffle(&mut rand::thread_rng());
+        sph.particles = particles.into_iter().collect();
+    }
+    sph
+}
+
+fn reorder(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dam_break_50k");
+    group.sample_size(10);
+    let cases = [("ordered", false, None), ("shuffled", true, None), ("shuffled_morton", true, Some(20))];
+    for &(name, shuffle, reorder_every) in &cases {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || dam_break(shuffle, reorder_every),
+                |mut sph| {
+                    for _ in 0..STEPS {
+                        sph.timestep().unwrap();
+                    }
+                    sph
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+// The density and pressure force passes over the same neighbor lists,
+// once over an array of particle structs like Sph used to keep and once
+// over one array per attribute like Particles. This isolates the storage
+// layout from the neighbor search, which dominates the step time above.
+const H: Real = 0.05;
+const SPACING: Real = H / 2.0;
+
+// the same attributes as sph::Particle, most are only carried along as
+// they were in the old loops
+#[allow(dead_code)]
+#[derive(Copy, Clone)]
+struct AosParticle {
+    id: usize,
+    pos: Vector2<Real>,
+    vel: Vector2<Real>,
+    m: Real,
+    rho: Real,
+    p: Real,
+    f: Vector2<Real>,
+    phase: usize,
+    shear_rate: Real,
+    vorticity: Real,
+    surface: bool,
+    normal: Vector2<Real>,
+    t: Real,
+    heat: Real,
+    xsph: Vector2<Real>,
+    fixed: bool,
+}
+
+#[derive(Clone, Default)]
+struct SoaParticles {
+    pos: Vec<Vector2<Real>>,
+    vel: Vec<Vector2<Real>>,
+    m: Vec<Real>,
+    rho: Vec<Real>,
+    p: Vec<Real>,
+    f: Vec<Vector2<Real>>,
+}
+
+fn poly6(r2: Real) -> Real {
+    4.0 / (std::f64::consts::PI as Real * H.powi(8)) * (H * H - r2).powi(3)
+}
+
+fn spiky_grad(rij: Vector2<Real>, r: Real) -> Vector2<Real> {
+    rij / r * 30.0 / (std::f64::consts::PI as Real * H.powi(5)) * (H - r) * (H - r)
+}
+
+fn aos_passes(ps: &mut [AosParticle], neighbors: &[Vec<usize>]) {
+    for i in 0..ps.len() {
+        let mut p = ps[i];
+        p.rho = 0.0;
+        for &j in &neighbors[i] {
+            let pj = ps[j];
+            p.rho += pj.m * poly6((pj.pos - p.pos).magnitude2());
+        }
+        p.p = (400.0 * (p.rho - 100.0)).max(0.0);
+        ps[i] = p;
+    }
+    for i in 0..ps.len() {
+        let mut p = ps[i];
+        p.f = Vector2::new(0.0, 0.0);
+        for &j in &neighbors[i] {
+            let pj = ps[j];
+            let rij = pj.pos - p.pos;
+            let r = rij.magnitude();
+            if j != i && r > 0.0 {
+                p.f += -spiky_grad(rij, r) * pj.m * (p.p + pj.p) / (2.0 * pj.rho);
+                p.f += 10.0 * pj.m * (pj.vel - p.vel) / pj.rho * (H - r);
+            }
+        }
+        ps[i] = p;
+    }
+}
+
+fn soa_passes(ps: &mut SoaParticles, neighbors: &[Vec<usize>]) {
+    for (i, list) in neighbors.iter().enumerate() {
+        let mut rho = 0.0;
+        for &j in list {
+            rho += ps.m[j] * poly6((ps.pos[j] - ps.pos[i]).magnitude2());
+        }
+        ps.rho[i] = rho;
+        ps.p[i] = (400.0 * (rho - 100.0)).max(0.0);
+    }
+    for (i, list) in neighbors.iter().enumerate() {
+        let mut f = Vector2::new(0.0, 0.0);
+        for &j in list {
+            let rij = ps.pos[j] - ps.pos[i];
+            let r = rij.magnitude();
+            if j != i && r > 0.0 {
+                f += -spiky_grad(rij, r) * ps.m[j] * (ps.p[i] + ps.p[j]) / (2.0 * ps.rho[j]);
+                f += 10.0 * ps.m[j] * (ps.vel[j] - ps.vel[i]) / ps.rho[j] * (H - r);
+            }
+        }
+        ps.f[i] = f;
+    }
+}
+
+// a shuffled block at the solver's spacing and its neighbor lists, from
+// a grid of H sized cells
+fn block(rows: usize) -> (Vec<Vector2<Real>>, Vec<Vec<usize>>) {
+    let mut pos: Vec<Vector2<Real>> = (0..rows * rows)
+        .map(|k| Vector2::new((k / rows) as Real, (k % rows) as Real) * SPACING)
+        .collect();
+    pos.shuffle(&mut rand::thread_rng());
+    let cell = |p: Vector2<Real>| ((p.x / H) as i64, (p.y / H) as i64);
+    let mut cells = std::collections::HashMap::new();
+    for (i, &p) in pos.iter().enumerate() {
+        cells.entry(cell(p)).or_insert_with(Vec::new).push(i);
+    }
+    let neighbors = pos
+        .iter()
+        .map(|&p| {
+            let (cx, cy) = cell(p);
+            let mut list = vec![];
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for &j in cells.get(&(cx + dx, cy + dy)).map_or(&[][..], |c| &c[..]) {
+                        if (pos[j] - p).magnitude2() < H * H {
+                            list.push(j);
+                        }
+                    }
+                }
+            }
+            list
+        })
+        .collect();
+    (pos, neighbors)
+}
+
+fn layout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("layout");
+    group.sample_size(10);
+    for &rows in &[60, 224] {
+        let (pos, neighbors) = block(rows);
+        let m = 100.0 * SPACING * SPACING;
+        let mut aos: Vec<AosParticle> = pos
+            .iter()
+            .enumerate()
+            .map(|(id, &pos)| AosParticle {
+                id,
+                pos,
+                vel: Vector2::new(0.0, 0.0),
+                m,
+                rho: 0.0,
+                p: 0.0,
+                f: Vector2::new(0.0, 0.0),
+                phase: 0,
+                shear_rate: 0.0,
+                vorticity: 0.0,
+                surface: false,
+                normal: Vector2::new(0.0, 0.0),
+                t: 0.0,
+                heat: 0.0,
+                xsph: Vector2::new(0.0, 0.0),
+                fixed: false,
+            })
+            .collect();
+        let n = pos.len();
+        let mut soa = SoaParticles {
+            pos,
+            vel: vec![Vector2::new(0.0, 0.0); n],
+            m: vec![m; n],
+            rho: vec![0.0; n],
+            p: vec![0.0; n],
+            f: vec![Vector2::new(0.0, 0.0); n],
+        };
+        group.bench_function(BenchmarkId::new("aos", n), |b| {
+            b.iter(|| aos_passes(black_box(&mut aos), &neighbors))
+        });
+        group.bench_function(BenchmarkId::new("soa", n), |b| {
+            b.iter(|| soa_passes(black_box(&mut soa), &neighbors))
+        });
+        // both layouts computed the same thing
+        assert!(aos.iter().zip(&soa.f).all(|(p, f)| (p.f - f).magnitude() <= 1e-3 * (1.0 + f.magnitude())));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, timestep, density, reorder, layout);
+criterion_main!(benches);
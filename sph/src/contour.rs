@@ -0,0 +1,325 @@
+use crate::sampling::{GridField, SampleGrid};
+use crate::{Real, Sph, H, H2, POLY6};
+use cgmath::{InnerSpace, Vector2};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polyline {
+    pub points: Vec<Vector2<Real>>,
+    // the last point connects back to the first
+    pub closed: bool,
+}
+
+// Iso lines of a sampled field and the triangles filling the region where
+// it is at or above the iso value.
+#[derive(Clone, Debug, Default)]
+pub struct Contours {
+    pub lines: Vec<Polyline>,
+    pub triangles: Vec<[Vector2<Real>; 3]>,
+}
+
+impl Contours {
+    // one row per point, in polyline order
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "line,closed,x,y")?;
+        for (i, line) in self.lines.iter().enumerate() {
+            for p in &line.points {
+                writeln!(w, "{},{},{},{}", i, line.closed as u8, p.x, p.y)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Closed lines become one even-odd filled path, so holes stay empty,
+    // open lines are stroked. y points up as in the simulation.
+    pub fn write_svg<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let points = self.lines.iter().flat_map(|l| l.points.iter());
+        let (min, max) = points.fold(
+            (Vector2::new(Real::INFINITY, Real::INFINITY), Vector2::new(Real::NEG_INFINITY, Real::NEG_INFINITY)),
+            |(min, max), p| (Vector2::new(min.x.min(p.x), min.y.min(p.y)), Vector2::new(max.x.max(p.x), max.y.max(p.y))),
+        );
+        let (min, size) = if min.x <= max.x { (min, max - min) } else { (Vector2::new(0.0, 0.0), Vector2::new(1.0, 1.0)) };
+        writeln!(
+            w,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">",
+            min.x,
+            -(min.y + size.y),
+            size.x,
+            size.y
+        )?;
+        writeln!(w, "<g transform=\"scale(1,-1)\">")?;
+        let mut d = String::new();
+        for line in self.lines.iter().filter(|l| l.closed) {
+            d.push_str(&path(&line.points));
+            d.push('Z');
+        }
+        if !d.is_empty() {
+            writeln!(w, "<path d=\"{}\" fill=\"#00a3ff\" fill-rule=\"evenodd\"/>", d)?;
+        }
+        for line in self.lines.iter().filter(|l| !l.closed) {
+            writeln!(
+                w,
+                "<path d=\"{}\" fill=\"none\" stroke=\"#00a3ff\" stroke-width=\"{}\"/>",
+                path(&line.points),
+                0.002 * size.x.max(size.y)
+            )?;
+        }
+        writeln!(w, "</g>")?;
+        writeln!(w, "</svg>")
+    }
+}
+
+fn path(points: &[Vector2<Real>]) -> String {
+    let mut d = String::new();
+    for (i, p) in points.iter().enumerate() {
+        d.push_str(&format!("{}{} {}", if i == 0 { "M" } else { "L" }, p.x, p.y));
+    }
+    d
+}
+
+// Cell corners and edges are visited counterclockwise from the bottom
+// left corner, edge k runs from corner k to corner k + 1. Edge points are
+// keyed by the grid edge they lie on, (i, j, 0) from (i, j) to (i + 1, j)
+// and (i, j, 1) from (i, j) to (i, j + 1), so neighboring cells agree.
+const CORNERS: [(usize, usize); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+const EDGES: [(usize, usize, usize); 4] = [(0, 0, 0), (1, 0, 1), (0, 1, 0), (0, 0, 1)];
+
+pub fn marching_squares(field: &GridField, iso: Real) -> Contours {
+    assert_eq!(field.components, 1);
+    let grid = &field.grid;
+    let value = |i: usize, j: usize| field.get(i, j)[0];
+    // NaN counts as outside
+    let inside = |i: usize, j: usize| value(i, j) >= iso;
+    let crossing = |(i, j, vertical): (usize, usize, usize)| {
+        let (a, b) = ((i, j), if vertical == 1 { (i, j + 1) } else { (i + 1, j) });
+        let (va, vb) = (value(a.0, a.1), value(b.0, b.1));
+        let t = if va.is_finite() && vb.is_finite() && va != vb { (iso - va) / (vb - va) } else { 0.5 };
+        let (pa, pb) = (grid.point(a.0, a.1), grid.point(b.0, b.1));
+        pa + (pb - pa) * t
+    };
+
+    let mut contours = Contours::default();
+    let mut segments = vec![];
+    for j in 0..grid.ny.saturating_sub(1) {
+        for i in 0..grid.nx.saturating_sub(1) {
+            let corner = |k: usize| (i + CORNERS[k].0, j + CORNERS[k].1);
+            let edge = |k: usize| (i + EDGES[k].0, j + EDGES[k].1, EDGES[k].2);
+            let ins: Vec<bool> = (0..4).map(|k| inside(corner(k).0, corner(k).1)).collect();
+            let crossed: Vec<usize> = (0..4).filter(|&k| ins[k] != ins[(k + 1) % 4]).collect();
+            if crossed.is_empty() {
+                if ins[0] {
+                    let p: Vec<_> = (0..4).map(|k| grid.point(corner(k).0, corner(k).1)).collect();
+                    contours.triangles.push([p[0], p[1], p[2]]);
+                    contours.triangles.push([p[0], p[2], p[3]]);
+                }
+                continue;
+            }
+
+            // the saddle is split unless the cell center is inside
+            let saddle = crossed.len() == 4;
+            let center = (0..4).map(|k| value(corner(k).0, corner(k).1)).sum::<Real>() / 4.0;
+            let joined = saddle && center >= iso;
+
+            // inside region, walking the cell boundary from an outside
+            // corner so a split saddle never wraps around; every piece is
+            // convex and fans from its first point
+            let start = (0..4).find(|&k| !ins[k]).unwrap();
+            let mut polygons = vec![vec![]];
+            for k in (start..start + 4).map(|k| k % 4) {
+                if ins[k] {
+                    let (ci, cj) = corner(k);
+                    polygons.last_mut().unwrap().push(grid.point(ci, cj));
+                }
+                if ins[k] != ins[(k + 1) % 4] {
+                    polygons.last_mut().unwrap().push(crossing(edge(k)));
+                    if saddle && !joined && ins[k] {
+                        polygons.push(vec![]);
+                    }
+                }
+            }
+            for polygon in polygons.iter().filter(|p| p.len() >= 3) {
+                for t in 1..polygon.len() - 1 {
+                    contours.triangles.push([polygon[0], polygon[t], polygon[t + 1]]);
+                }
+            }
+
+            // each crossing pairs with the next one along the boundary
+            // that closes the same inside region
+            let pairs = match (crossed.len(), joined, ins[0]) {
+                (2, ..) => vec![(crossed[0], crossed[1])],
+                (_, false, true) | (_, true, false) => vec![(3, 0), (1, 2)],
+                _ => vec![(0, 1), (2, 3)],
+            };
+            for (a, b) in pairs {
+                segments.push((edge(a), edge(b)));
+            }
+        }
+    }
+
+    contours.lines = stitch(&segments, crossing);
+    contours
+}
+
+type EdgeKey = (usize, usize, usize);
+
+// joins segments that share an edge point into polylines
+fn stitch<F: Fn(EdgeKey) -> Vector2<Real>>(segments: &[(EdgeKey, EdgeKey)], point: F) -> Vec<Polyline> {
+    let mut at: HashMap<EdgeKey, Vec<usize>> = HashMap::new();
+    for (s, &(a, b)) in segments.iter().enumerate() {
+        at.entry(a).or_default().push(s);
+        at.entry(b).or_default().push(s);
+    }
+    let mut used = vec![false; segments.len()];
+    let mut lines = vec![];
+    // open lines start at an end, what is left are loops
+    let mut starts: Vec<usize> = (0..segments.len())
+        .filter(|&s| at[&segments[s].0].len() == 1 || at[&segments[s].1].len() == 1)
+        .collect();
+    starts.extend(0..segments.len());
+    for start in starts {
+        if used[start] {
+            continue;
+        }
+        let (a, b) = segments[start];
+        let (mut key, closed) = if at[&b].len() == 1 { (a, false) } else if at[&a].len() == 1 { (b, false) } else { (b, true) };
+        let first = if key == b { a } else { b };
+        let mut keys = vec![first, key];
+        used[start] = true;
+        while let Some(&next) = at[&key].iter().find(|&&s| !used[s]) {
+            used[next] = true;
+            let (a, b) = segments[next];
+            key = if a == key { b } else { a };
+            if closed && key == first {
+                break;
+            }
+            keys.push(key);
+        }
+        lines.push(Polyline { points: keys.into_iter().map(&point).collect(), closed });
+    }
+    lines
+}
+
+impl Sph {
+    // SPH color field, about 1 inside the fluid and 0 away from it
+    pub fn color_field(&self, grid: &SampleGrid) -> GridField {
+        let search = self.search_grid();
+        let ps = &self.particles;
+        let mut values = Vec::with_capacity(grid.nx * grid.ny);
+        for j in 0..grid.ny {
+            for i in 0..grid.nx {
+                let x = grid.point(i, j);
+                let mut c = 0.0;
+                for k in search.candidates(x) {
+                    let r2 = self.delta(x, ps.pos[k]).magnitude2();
+                    if r2 < H2 && ps.rho[k] > 0.0 {
+                        c += ps.m[k] / ps.rho[k] * POLY6 * (H2 - r2) * (H2 - r2) * (H2 - r2);
+                    }
+                }
+                values.push(c);
+            }
+        }
+        GridField { grid: *grid, components: 1, values }
+    }
+
+    // Fluid outline from the color field at half its interior value, sampled
+    // every `spacing` over the domain plus one kernel radius so contours
+    // away from periodic edges are closed.
+    pub fn contours(&self, spacing: Real) -> Contours {
+        let domain = self.params.domain;
+        let origin = Vector2::new(-H, -H);
+        let nx = ((domain.width + 2.0 * H) / spacing).ceil() as usize + 1;
+        let ny = ((domain.height + 2.0 * H) / spacing).ceil() as usize + 1;
+        let grid = SampleGrid { origin, spacing: Vector2::new(spacing, spacing), nx, ny };
+        marching_squares(&self.color_field(&grid), 0.5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{block, SPACING};
+
+    fn area(points: &[Vector2<Real>]) -> Real {
+        let n = points.len();
+        (0..n).map(|i| points[i].x * points[(i + 1) % n].y - points[(i + 1) % n].x * points[i].y).sum::<Real>() / 2.0
+    }
+
+    fn triangle_area(triangles: &[[Vector2<Real>; 3]]) -> Real {
+        triangles.iter().map(|t| area(t).abs()).sum()
+    }
+
+    #[test]
+    fn circle() {
+        let grid = SampleGrid { origin: Vector2::new(0.0, 0.0), spacing: Vector2::new(0.02, 0.02), nx: 51, ny: 51 };
+        let center = Vector2::new(0.5, 0.5);
+        let mut values = vec![];
+        for j in 0..grid.ny {
+            for i in 0..grid.nx {
+                values.push(0.3 - (grid.point(i, j) - center).magnitude());
+            }
+        }
+        let contours = marching_squares(&GridField { grid, components: 1, values }, 0.0);
+        assert_eq!(contours.lines.len(), 1);
+        let line = &contours.lines[0];
+        assert!(line.closed);
+        assert!(line.points.iter().all(|p| ((p - center).magnitude() - 0.3).abs() < 0.002));
+        let circle = std::f64::consts::PI as Real * 0.09;
+        assert!((area(&line.points).abs() - circle).abs() < 0.01 * circle);
+        assert!((triangle_area(&contours.triangles) - area(&line.points).abs()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ring_has_a_hole() {
+        let grid = SampleGrid { origin: Vector2::new(0.0, 0.0), spacing: Vector2::new(0.02, 0.02), nx: 51, ny: 51 };
+        let center = Vector2::new(0.5, 0.5);
+        let mut values = vec![];
+        for j in 0..grid.ny {
+            for i in 0..grid.nx {
+                values.push(0.1 - ((grid.point(i, j) - center).magnitude() - 0.25).abs());
+            }
+        }
+        let contours = marching_squares(&GridField { grid, components: 1, values }, 0.0);
+        assert_eq!(contours.lines.len(), 2);
+        let ring: Real = contours.lines.iter().map(|l| area(&l.points).abs()).fold(0.0, |a, b| (a - b).abs());
+        assert!((triangle_area(&contours.triangles) - ring).abs() < 1e-4);
+
+        let mut svg = vec![];
+        contours.write_svg(&mut svg).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert_eq!(svg.matches("<path").count(), 1);
+        assert_eq!(svg.matches('Z').count(), 2);
+    }
+
+    #[test]
+    fn saddle_follows_the_center() {
+        let grid = SampleGrid { origin: Vector2::new(0.0, 0.0), spacing: Vector2::new(1.0, 1.0), nx: 2, ny: 2 };
+        let field = GridField { grid, components: 1, values: vec![1.0, 0.0, 0.0, 1.0] };
+        // joined through the center, the square minus two corners
+        let joined = marching_squares(&field, 0.4);
+        assert_eq!(joined.lines.len(), 2);
+        assert!((triangle_area(&joined.triangles) - 0.84).abs() < 1e-5);
+        // split into two corner triangles
+        let split = marching_squares(&field, 0.6);
+        assert_eq!(split.lines.len(), 2);
+        assert!((triangle_area(&split.triangles) - 0.16).abs() < 1e-5);
+    }
+
+    #[test]
+    fn block_outline() {
+        let mut sph = Sph::new(0);
+        sph.particles = block(10, Vector2::new(0.3, 0.3));
+        sph.timestep().unwrap();
+        let contours = sph.contours(0.01);
+        assert_eq!(contours.lines.len(), 1);
+        assert!(contours.lines[0].closed);
+        // about the block's own area, 10 spacings on a side
+        let block = (10.0 * SPACING) * (10.0 * SPACING);
+        let filled = triangle_area(&contours.triangles);
+        assert!((filled - block).abs() < 0.2 * block, "{} vs {}", filled, block);
+
+        let mut csv = vec![];
+        contours.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 1 + contours.lines[0].points.len());
+    }
+}
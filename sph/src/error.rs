@@ -0,0 +1,47 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Quantity {
+    Density,
+    Pressure,
+    Force,
+    Velocity,
+    Position,
+    Temperature,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Quantity::Density => "density",
+            Quantity::Pressure => "pressure",
+            Quantity::Force => "force",
+            Quantity::Velocity => "velocity",
+            Quantity::Position => "position",
+            Quantity::Temperature => "temperature",
+        };
+        f.write_str(name)
+    }
+}
+
+// First particle, in index order, that went NaN or infinite during a step.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StepError {
+    pub step: usize,
+    pub index: usize,
+    pub id: usize,
+    pub quantity: Quantity,
+}
+
+impl fmt::Display for StepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "step {}: {} of particle {} (id {}) is not finite",
+            self.step, self.quantity, self.index, self.id
+        )
+    }
+}
+
+impl Error for StepError {}
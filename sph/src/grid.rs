@@ -0,0 +1,225 @@
+use crate::sources::InflowOutflow;
+use crate::sph3d::Box3d;
+use crate::{Domain, Real};
+use cgmath::{Vector2, Vector3};
+
+// Uniform grid over the domain with cells at least `h` wide, so the
+// neighbors of a particle are all in its own or the adjacent cells. A 2D
+// grid has a single layer of cells along z. Between inflow and outflow
+// planes the x axis is periodic, so particles near the inflow see the ones
+// about to leave.
+// Particles are bucketed with a counting sort, `order[start[c]..start[c + 1]]`
+// holds the indices of the particles in cell c.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Grid {
+    n: [usize; 3],
+    origin: [Real; 3],
+    cell_size: [Real; 3],
+    periodic: [bool; 3],
+    start: Vec<usize>,
+    order: Vec<usize>,
+}
+
+impl Grid {
+    pub(crate) fn build(
+        &mut self,
+        domain: &Domain,
+        channel: Option<InflowOutflow>,
+        h: Real,
+        pos: &[Vector2<Real>],
+    ) {
+        let (origin, size, periodic) = match channel {
+            Some(io) => ([io.inflow, 0.0, 0.0], [io.outflow - io.inflow, domain.height, 0.0], true),
+            None => ([0.0; 3], [domain.width, domain.height, 0.0], domain.periodic_x),
+        };
+        let periodic = [periodic, domain.periodic_y, false];
+        self.bucket(origin, size, periodic, h, pos.iter().map(|p| [p.x, p.y, 0.0]));
+    }
+
+    pub(crate) fn build_3d(&mut self, domain: &Box3d, h: Real, pos: &[Vector3<Real>]) {
+        let size = [domain.width, domain.height, domain.depth];
+        self.bucket([0.0; 3], size, [false; 3], h, pos.iter().map(|&p| p.into()));
+    }
+
+    fn bucket<I>(&mut self, origin: [Real; 3], size: [Real; 3], periodic: [bool; 3], h: Real, points: I)
+    where
+        I: Iterator<Item = [Real; 3]> + Clone,
+    {
+        self.origin = origin;
+        for (axis, &size) in size.iter().enumerate() {
+            self.n[axis] = ((size / h) as usize).max(1);
+            // never below h, which only matters for the flat z axis in 2D
+            self.cell_size[axis] = (size / self.n[axis] as Real).max(h);
+        }
+        self.periodic = periodic;
+
+        self.start.clear();
+        self.start.resize(self.n[0] * self.n[1] * self.n[2] + 1, 0);
+        for p in points.clone() {
+            let c = self.index(self.cell_at(p));
+            self.start[c + 1] += 1;
+        }
+        for c in 1..self.start.len() {
+            self.start[c] += self.start[c - 1];
+        }
+        let mut next = self.start.clone();
+        self.order.clear();
+        self.order.resize(self.start[self.start.len() - 1], 0);
+        for (i, p) in points.enumerate() {
+            let c = self.index(self.cell_at(p));
+            self.order[next[c]] = i;
+            next[c] += 1;
+        }
+    }
+
+    // particles outside the domain are put in the nearest cell
+    fn cell_at(&self, p: [Real; 3]) -> [usize; 3] {
+        let mut cell = [0; 3];
+        for axis in 0..3 {
+            let c = ((p[axis] - self.origin[axis]) / self.cell_size[axis]).floor().max(0.0) as usize;
+            cell[axis] = c.min(self.n[axis] - 1);
+        }
+        cell
+    }
+
+    fn index(&self, [cx, cy, cz]: [usize; 3]) -> usize {
+        (cz * self.n[1] + cy) * self.n[0] + cx
+    }
+
+    pub(crate) fn cell(&self, p: Vector2<Real>) -> (usize, usize) {
+        let [cx, cy, _] = self.cell_at([p.x, p.y, 0.0]);
+        (cx, cy)
+    }
+
+    // candidates for the neighbors of a particle at `p`, a superset of
+    // the particles within h
+    pub(crate) fn candidates(&self, p: Vector2<Real>) -> impl Iterator<Item = usize> + '_ {
+        self.candidates_at([p.x, p.y, 0.0])
+    }
+
+    pub(crate) fn candidates_3d(&self, p: Vector3<Real>) -> impl Iterator<Item = usize> + '_ {
+        self.candidates_at(p.into())
+    }
+
+    fn candidates_at(&self, p: [Real; 3]) -> impl Iterator<Item = usize> + '_ {
+        let [cx, cy, cz] = self.cell_at(p);
+        let xs = adjacent(cx, self.n[0], self.periodic[0]);
+        let ys = adjacent(cy, self.n[1], self.periodic[1]);
+        let zs = adjacent(cz, self.n[2], self.periodic[2]);
+        zs.into_iter()
+            .flat_map(move |z| ys.clone().into_iter().map(move |y| [z, y]))
+            .flat_map(move |[z, y]| xs.clone().into_iter().map(move |x| self.index([x, y, z])))
+            .flat_map(move |c| self.order[self.start[c]..self.start[c + 1]].iter().cloned())
+    }
+}
+
+// cell and its neighbors along one axis, wrapped on periodic axes and
+// without repeats when there are fewer than 3 cells
+fn adjacent(c: usize, n: usize, periodic: bool) -> Vec<usize> {
+    let mut cells = Vec::with_capacity(3);
+    for a in [c as isize - 1, c as isize, c as isize + 1].iter() {
+        let a = if periodic {
+            a.rem_euclid(n as isize)
+        } else if *a < 0 || *a >= n as isize {
+            continue;
+        } else {
+            *a
+        } as usize;
+        if !cells.contains(&a) {
+            cells.push(a);
+        }
+    }
+    cells
+}
+
+// interleaves the bits of the cell coordinates, cells that are close in
+// space get close codes
+pub(crate) fn morton(cx: usize, cy: usize) -> u64 {
+    fn spread(v: usize) -> u64 {
+        let mut v = v as u64 & 0xffff_ffff;
+        v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+        v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+        v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+        v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+        v = (v | (v << 1)) & 0x5555_5555_5555_5555;
+        v
+    }
+    spread(cx) | (spread(cy) << 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn candidates_cover_neighbors() {
+        let domain = Domain { width: 1.0, height: 0.5, periodic_x: true, periodic_y: false };
+        let pos: Vec<_> = (0..200)
+            .map(|i| Vector2::new((i as Real * 0.37) % 1.0, (i as Real * 0.21) % 0.5))
+            .collect();
+        let mut grid = Grid::default();
+        grid.build(&domain, None, 0.1, &pos);
+        for &p in &pos {
+            let mut found: Vec<_> = grid.candidates(p).collect();
+            found.sort_unstable();
+            let n = found.len();
+            found.dedup();
+            assert_eq!(found.len(), n);
+            let near = pos.iter().enumerate().filter(|(_, &q)| {
+                let dx = (q.x - p.x) - (q.x - p.x).round();
+                let dy = q.y - p.y;
+                dx * dx + dy * dy < 0.01
+            });
+            for (j, _) in near {
+                assert!(found.binary_search(&j).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn candidates_wrap_across_inflow_and_outflow() {
+        let domain = Domain { width: 1.0, height: 0.5, periodic_x: false, periodic_y: false };
+        let channel = InflowOutflow { inflow: 0.2, outflow: 0.8, velocity: None };
+        let pos = vec![Vector2::new(0.21, 0.25), Vector2::new(0.79, 0.25), Vector2::new(0.5, 0.25)];
+        let mut grid = Grid::default();
+        grid.build(&domain, Some(channel), 0.1, &pos);
+        assert!(grid.candidates(pos[0]).any(|j| j == 1));
+        assert!(grid.candidates(pos[1]).any(|j| j == 0));
+        assert!(!grid.candidates(pos[0]).any(|j| j == 2));
+
+        // without the channel the planes are just far apart
+        grid.build(&domain, None, 0.1, &pos);
+        assert!(!grid.candidates(pos[0]).any(|j| j == 1));
+    }
+
+    #[test]
+    fn candidates_cover_neighbors_3d() {
+        let domain = Box3d { width: 1.0, height: 0.5, depth: 0.3 };
+        let pos: Vec<_> = (0..300)
+            .map(|i| {
+                let i = i as Real;
+                Vector3::new((i * 0.37) % 1.0, (i * 0.21) % 0.5, (i * 0.13) % 0.3)
+            })
+            .collect();
+        let mut grid = Grid::default();
+        grid.build_3d(&domain, 0.1, &pos);
+        for &p in &pos {
+            let found: Vec<_> = grid.candidates_3d(p).collect();
+            for (j, &q) in pos.iter().enumerate() {
+                let d = q - p;
+                if d.x * d.x + d.y * d.y + d.z * d.z < 0.01 {
+                    assert!(found.contains(&j));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn morton_interleaves() {
+        assert_eq!(morton(0, 0), 0);
+        assert_eq!(morton(1, 0), 1);
+        assert_eq!(morton(0, 1), 2);
+        assert_eq!(morton(3, 3), 15);
+        assert_eq!(morton(4, 0), 16);
+    }
+}
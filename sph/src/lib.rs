@@ -1,175 +1,1253 @@
+// casts to f32 are no-ops unless the f64 feature is on
+#![allow(clippy::unnecessary_cast)]
+// is_multiple_of needs Rust 1.87
+#![allow(clippy::manual_is_multiple_of)]
+
+pub mod contour;
+mod error;
+mod finite;
+mod grid;
+mod params;
+mod particles;
+pub mod sampling;
+pub mod sources;
+pub mod sph3d;
+mod stats;
+pub mod thermal;
+pub mod tracers;
+pub mod viscosity;
+
+pub use crate::error::{Quantity, StepError};
+pub use crate::params::{ArtificialViscosity, Domain, FreeSurface, NonFinitePolicy, Params};
+pub use crate::particles::Particles;
+pub use crate::sph3d::Sph3d;
+pub use crate::stats::StepStats;
+
 use cgmath::Rotation3;
 use cgmath::Vector2;
 use cgmath::InnerSpace;
-use utils::Instance;
+use grid::Grid;
+use std::io::{self, Write};
+use utils::{Instance, Vertex};
 use rand::Rng;
+use sampling::Probe;
+use sources::{Emitter, Sink};
+use tracers::Tracers;
+use viscosity::{Newtonian, Phase};
+
+// scalar type of all solver math, positions are converted to f32 only
+// when building instances
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+#[cfg(feature = "f64")]
+pub type Real = f64;
+
+const PI: Real = std::f64::consts::PI as Real;
 
 // VIEW: starts at 0,0 at top left corner
-const VIEW_WIDTH: f32 = 1.0;
-const VIEW_HEIGHT: f32 = 1.0;
+const VIEW_WIDTH: Real = 1.0;
+const VIEW_HEIGHT: Real = 1.0;
 
-const DT: f32 = 0.0008;
-const R: f32 = 0.01;
-const DIAM2: f32 = (2.0 * R) * (2.0 * R);
+const DT: Real = 0.0008;
+// R is the drawn particle radius, H the kernel support radius
+const R: Real = 0.01;
+const H: Real = 0.05;
+const H2: Real = H * H;
+const SPACING: Real = H / 2.0;
 
-const POLY6: f32 = 315.0/(65.0 * 3.14 * R * R * R * R * R * R * R * R * R);
-const REST_DENS: f32 = 100.0;
-const GAS_CONST: f32 = 600.0;
-const VISC: f32 = 250.0;
-const VISC_LAP: f32 = 45.0/(3.14 * R * R * R * R * R * R);
-const SPIKY_GRAD: f32 = -45.0/(3.14 * R * R * R * R * R * R);
+// 2D normalizations of the Muller et al. kernels
+const POLY6: Real = 4.0/(PI * H2 * H2 * H2 * H2);
+const REST_DENS: Real = 100.0;
+const MASS: Real = REST_DENS * SPACING * SPACING;
+// stiffness, the speed of sound is its square root and must stay below
+// about 0.4 * H / DT for stability
+const GAS_CONST: Real = 400.0;
+const VISC: Real = 10.0;
+const POLY6_GRAD: Real = -24.0/(PI * H2 * H2 * H2 * H2);
+const SPIKY_GRAD: Real = -30.0/(PI * H2 * H2 * H);
+// neighbors summed side by side in the density pass, 8 fills an AVX
+// register in single precision
+const LANES: usize = 8;
 
-const GRAVITY: f32 = -9.8;
-const G: Vector2<f32> = Vector2{ x: 0.0, y: GRAVITY * 300.0 };
-const BOUND_DAMPING: f32 = -0.5;
+const GRAVITY: Real = -9.8;
+const G: Vector2<Real> = Vector2{ x: 0.0, y: GRAVITY };
+const BOUND_DAMPING: Real = -0.5;
+
+// What the renderer needs from a solver, 2D or 3D.
+pub trait Simulation {
+    fn timestep(&mut self) -> Result<(), StepError>;
+    fn instances(&self) -> Vec<Instance>;
+    // per particle values in instance order, for coloring
+    fn scalars(&self, field: Field) -> Vec<f32>;
+    // triangle list filling the fluid, in instance coordinates, empty
+    // when there is no 2D outline
+    fn surface_mesh(&self) -> Vec<Vertex> {
+        vec![]
+    }
+    fn is_3d(&self) -> bool {
+        false
+    }
+}
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Particle {
-    pos: Vector2<f32>,
-    vel: Vector2<f32>,
-    m: f32,
-    rho: f32,
-    p: f32,
-    f: Vector2<f32>,
+    id: usize,
+    pos: Vector2<Real>,
+    vel: Vector2<Real>,
+    m: Real,
+    rho: Real,
+    p: Real,
+    f: Vector2<Real>,
+    phase: usize,
+    shear_rate: Real,
+    vorticity: Real,
+    surface: bool,
+    normal: Vector2<Real>,
+    t: Real,
+    heat: Real,
+    xsph: Vector2<Real>,
+    fixed: bool,
 }
 
 impl Particle {
-    fn new(x: f32, y: f32) -> Self {
+    fn new(x: Real, y: Real) -> Self {
         let pos = Vector2::new(x, y);
         let vel = Vector2::new(0.0, 0.0);
-        let m = 1.0;
+        let m = MASS;
         let rho = 0.0;
         let p = 0.0;
         let f = Vector2::new(0.0, 0.0);
-        Particle { pos, vel, m, rho, p, f }
+        let phase = 0;
+        let shear_rate = 0.0;
+        let vorticity = 0.0;
+        let surface = false;
+        let normal = Vector2::new(0.0, 0.0);
+        let t = 0.0;
+        let heat = 0.0;
+        let xsph = Vector2::new(0.0, 0.0);
+        let fixed = false;
+        // assigned by Particles::push
+        let id = 0;
+        Particle { id, pos, vel, m, rho, p, f, phase, shear_rate, vorticity, surface, normal, t, heat, xsph, fixed }
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Field {
+    Speed,
+    Density,
+    Pressure,
+    Phase,
+    ShearRate,
+    Temperature,
+    Vorticity,
+    Surface,
+    // components of the outward surface normal, zero inside the fluid
+    NormalX,
+    NormalY,
+}
+
 pub struct Sph {
-    pub particles: Vec<Particle>,
+    pub particles: Particles,
+    pub phases: Vec<Phase>,
+    pub params: Params,
+    pub emitters: Vec<Emitter>,
+    pub sinks: Vec<Sink>,
+    pub tracers: Tracers,
+    pub probes: Vec<Probe>,
+    // indices within H of each particle, itself included
+    neighbors: Vec<Vec<usize>>,
+    grid: Grid,
+    steps: usize,
+    stats_csv: Option<Box<dyn Write + Send>>,
 }
 
 impl Sph {
     pub fn new(number_instances_per_row: i32) -> Self {
-        let mut particles = vec![];
+        let mut particles = Particles::default();
 
-        
-        let center_x = VIEW_WIDTH / 2.0;
         let quarter_x = VIEW_WIDTH / 4.0;
-        let dist_x = center_x / number_instances_per_row as f32;
+        let dist_x = SPACING;
 
-        let center_y = VIEW_HEIGHT / 2.0;
         let quarter_y = VIEW_HEIGHT / 4.0;
-        let dist_y = center_y / number_instances_per_row as f32;
+        let dist_y = SPACING;
 
         for i in 0..number_instances_per_row {
-            let x = quarter_x + i as f32 * dist_x;
+            let x = quarter_x + i as Real * dist_x;
             for j in 0..number_instances_per_row {
-                let y = quarter_y + j as f32 * dist_y;
+                let y = quarter_y + j as Real * dist_y;
                 let mut rng = rand::thread_rng();
-                let jitter = rng.gen_range(-0.02, 0.02);
+                let jitter = rng.gen_range(-0.05 * SPACING, 0.05 * SPACING);
 
                 let p = Particle::new(x + jitter, y);
                 particles.push(p);
             }
         }
-       
-        
-        
-        particles.push(Particle::new(0.375, 0.5));
-        particles.push(Particle::new(0.525, 0.5));
 
-        Self { particles }
+        let phases = vec![Phase::new(Newtonian { mu: VISC })];
+
+        let params = Params::default();
+
+        Self {
+            particles,
+            phases,
+            params,
+            emitters: vec![],
+            sinks: vec![],
+            tracers: Tracers::default(),
+            probes: vec![],
+            neighbors: vec![],
+            grid: Grid::default(),
+            steps: 0,
+            stats_csv: None,
+        }
     }
 
-    pub fn integrate(&mut self, index: usize) {
-        let mut p = self.particles[index];
-        
-        p.rho = 0.0;
-        for &pi in &self.particles {
-            let rij = pi.pos - p.pos;
-            let r2 = rij.magnitude2();
+    pub fn add_phase(&mut self, phase: Phase) -> usize {
+        self.phases.push(phase);
+        self.phases.len() - 1
+    }
+
+    pub fn set_phase(&mut self, index: usize, phase: usize) {
+        assert!(phase < self.phases.len(), "phase {} does not exist", phase);
+        self.particles.phase[index] = phase;
+    }
+
+    pub fn shear_rate(&self, index: usize) -> Real {
+        self.particles.shear_rate[index]
+    }
+
+    // records the particle at `index` after every step from now on,
+    // returns its id
+    pub fn trace(&mut self, index: usize) -> usize {
+        let id = self.particles.id(index);
+        self.tracers.tag(id);
+        id
+    }
+
+    // simulated time
+    pub fn time(&self) -> Real {
+        self.steps as Real * DT
+    }
+
+    // densities and neighbor counts are the ones the last step used,
+    // particles added since are left out of both
+    pub fn stats(&self) -> StepStats {
+        let ps = &self.particles;
+        let mut stats = StepStats {
+            step: self.steps,
+            time: self.time(),
+            kinetic_energy: 0.0,
+            potential_energy: 0.0,
+            momentum: Vector2::new(0.0, 0.0),
+            angular_momentum: 0.0,
+            max_density_error: 0.0,
+            avg_density_error: 0.0,
+            max_velocity: 0.0,
+            min_neighbors: usize::MAX,
+            cfl: 0.0,
+        };
+        let mut stepped = 0;
+        for i in 0..ps.len() {
+            let (m, pos, vel) = (ps.m[i], ps.pos[i], ps.vel[i]);
+            stats.kinetic_energy += 0.5 * m * vel.magnitude2();
+            stats.potential_energy -= m * self.params.gravity.dot(pos);
+            stats.momentum += m * vel;
+            stats.angular_momentum += m * (pos.x * vel.y - pos.y * vel.x);
+            stats.max_velocity = stats.max_velocity.max(vel.magnitude());
+            if ps.neighbor_count[i] == 0 {
+                continue;
+            }
+            stepped += 1;
+            let density_error = (ps.rho[i] - REST_DENS).abs() / REST_DENS;
+            stats.max_density_error = stats.max_density_error.max(density_error);
+            stats.avg_density_error += density_error;
+            // not counting the particle itself
+            stats.min_neighbors = stats.min_neighbors.min(ps.neighbor_count[i] - 1);
+        }
+        if stepped > 0 {
+            stats.avg_density_error /= stepped as Real;
+        } else {
+            stats.min_neighbors = 0;
+        }
+        // acoustic CFL number, information must not cross more than a
+        // fraction of the support radius per step
+        stats.cfl = DT * (GAS_CONST.sqrt() + stats.max_velocity) / H;
+        stats
+    }
+
+    // writes a CSV row of `stats()` after every step, the writer has to be
+    // Send so that the solver stays Send
+    pub fn write_stats_csv<W: Write + Send + 'static>(&mut self, mut w: W) -> io::Result<()> {
+        StepStats::write_csv_header(&mut w)?;
+        self.stats_csv = Some(Box::new(w));
+        Ok(())
+    }
+
+    // fixed particles keep their position and velocity, rows of them make
+    // no-slip walls
+    pub fn fix(&mut self, index: usize) {
+        self.particles.fixed[index] = true;
+    }
+
+    // minimum image vector from a to b on periodic axes
+    fn delta(&self, a: Vector2<Real>, b: Vector2<Real>) -> Vector2<Real> {
+        let d = b - a;
+        let (mut dx, mut dy) = ([d.x], [d.y]);
+        self.wrap(&mut dx, &mut dy);
+        Vector2::new(dx[0], dy[0])
+    }
+
+    // turns offsets into minimum image ones, over slices so that the
+    // density pass can wrap a whole chunk of neighbors at once
+    fn wrap(&self, dx: &mut [Real], dy: &mut [Real]) {
+        let domain = &self.params.domain;
+        if domain.periodic_x {
+            for d in dx.iter_mut() {
+                *d -= domain.width * (*d / domain.width).round();
+            }
+        }
+        if domain.periodic_y {
+            for d in dy.iter_mut() {
+                *d -= domain.height * (*d / domain.height).round();
+            }
+        }
+        // the channel wraps like a periodic axis between its planes
+        if let Some(io) = self.params.inflow_outflow {
+            let length = io.outflow - io.inflow;
+            for d in dx.iter_mut() {
+                *d -= length * (*d / length).round();
+            }
+        }
+    }
+
+    pub fn is_surface(&self, index: usize) -> bool {
+        self.particles.surface[index]
+    }
+
+    // outward unit normal of a surface particle, zero inside the fluid and
+    // for isolated particles
+    pub fn normal(&self, index: usize) -> Vector2<Real> {
+        self.particles.normal[index]
+    }
+
+    pub fn temperature(&self, index: usize) -> Real {
+        self.particles.t[index]
+    }
+
+    pub fn set_temperature(&mut self, index: usize, t: Real) {
+        self.particles.t[index] = t;
+    }
+
+    pub fn field(&self, field: Field) -> Vec<Real> {
+        self.particles
+            .iter()
+            .map(|p| match field {
+                Field::Speed => p.vel.magnitude(),
+                Field::Density => p.rho,
+                Field::Pressure => p.p,
+                Field::Phase => p.phase as Real,
+                Field::ShearRate => p.shear_rate,
+                Field::Temperature => p.t,
+                Field::Vorticity => p.vorticity,
+                Field::Surface => if p.surface { 1.0 } else { 0.0 },
+                Field::NormalX => p.normal.x,
+                Field::NormalY => p.normal.y,
+            })
+            .collect()
+    }
+
+    // grid search, the passes below only visit the lists it leaves behind
+    pub fn find_neighbors(&mut self) {
+        self.grid.build(&self.params.domain, self.params.inflow_outflow, H, &self.particles.pos);
+        let pos = &self.particles.pos;
+        let mut neighbors = std::mem::take(&mut self.neighbors);
+        neighbors.resize_with(pos.len(), Vec::new);
+        for (i, list) in neighbors.iter_mut().enumerate() {
+            list.clear();
+            for j in self.grid.candidates(pos[i]) {
+                if self.delta(pos[i], pos[j]).magnitude2() < H2 {
+                    list.push(j);
+                }
+            }
+        }
+        self.particles.neighbor_count = neighbors.iter().map(Vec::len).collect();
+        self.neighbors = neighbors;
+    }
+
+    // sorts storage by the Morton code of each particle's grid cell so that
+    // neighbors sit close together in memory, ids are kept
+    pub fn reorder(&mut self) {
+        self.grid.build(&self.params.domain, self.params.inflow_outflow, H, &self.particles.pos);
+        let grid = &self.grid;
+        let codes: Vec<u64> = self
+            .particles
+            .pos
+            .iter()
+            .map(|&p| {
+                let (cx, cy) = grid.cell(p);
+                grid::morton(cx, cy)
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..codes.len()).collect();
+        order.sort_by_key(|&i| codes[i]);
+        self.particles.permute(&order);
+    }
+
+    pub fn density(&mut self, index: usize) {
+        let ps = &self.particles;
+        let pos = ps.pos[index];
 
-            if r2 < DIAM2 {
-                p.rho += p.m * POLY6 * (DIAM2 - r2) * (DIAM2 - r2) * (DIAM2 - r2);
+        // neighbors are gathered LANES at a time into fixed size arrays so
+        // that the kernel sum compiles to vector instructions, the last
+        // chunk is padded with massless lanes
+        let mut sum = [0.0; LANES];
+        for chunk in self.neighbors[index].chunks(LANES) {
+            let (mut dx, mut dy, mut m) = ([H; LANES], [0.0; LANES], [0.0; LANES]);
+            for (lane, &j) in chunk.iter().enumerate() {
+                dx[lane] = ps.pos[j].x - pos.x;
+                dy[lane] = ps.pos[j].y - pos.y;
+                m[lane] = ps.m[j];
+            }
+            self.wrap(&mut dx, &mut dy);
+            for lane in 0..LANES {
+                let q = (H2 - dx[lane] * dx[lane] - dy[lane] * dy[lane]).max(0.0);
+                sum[lane] += m[lane] * POLY6 * q * q * q;
             }
         }
+        let rho: Real = sum.iter().sum();
+
+        self.particles.rho[index] = rho;
+        // no tension, particles at the free surface would otherwise clump
+        self.particles.p[index] = (GAS_CONST * (rho - REST_DENS)).max(0.0);
+    }
 
-        p.p = GAS_CONST * (p.rho - REST_DENS);
+    // flags particles with a large color field gradient, or too few
+    // neighbors to have one, as free surface
+    pub fn surface(&mut self, index: usize) {
+        let ps = &self.particles;
+        let pos = ps.pos[index];
+
+        // points into the fluid
+        let mut grad_c = Vector2{ x: 0.0, y: 0.0 };
+        for &j in &self.neighbors[index] {
+            if ps.pos[j] == pos {
+                continue;
+            }
+
+            let rij = self.delta(pos, ps.pos[j]);
+            let r = rij.magnitude();
+
+            if r < H {
+                grad_c += -rij.normalize() * SPIKY_GRAD * (H - r) * (H - r) * ps.m[j] / ps.rho[j];
+            }
+        }
+
+        let threshold = self.params.free_surface;
+        let g = grad_c.magnitude();
+        self.particles.surface[index] =
+            g * H > threshold.gradient || self.neighbors[index].len() < threshold.min_neighbors;
+        self.particles.normal[index] = if self.particles.surface[index] && g > 0.0 {
+            -grad_c / g
+        } else {
+            Vector2::new(0.0, 0.0)
+        };
+    }
+
+    pub fn shear(&mut self, index: usize) {
+        let ps = &self.particles;
+        let pos = ps.pos[index];
+        let vel = ps.vel[index];
+
+        // velocity gradient, grad_v[a][b] = d v_b / d x_a
+        let mut grad_v: [[Real; 2]; 2] = [[0.0; 2]; 2];
+        for &j in &self.neighbors[index] {
+            if ps.pos[j] == pos {
+                continue;
+            }
+
+            let rij = self.delta(pos, ps.pos[j]);
+            let r = rij.magnitude();
+
+            if r < H {
+                let grad_w = -rij.normalize() * SPIKY_GRAD * (H - r) * (H - r);
+                let dv = (ps.vel[j] - vel) * ps.m[j] / ps.rho[j];
+                grad_v[0][0] += grad_w.x * dv.x;
+                grad_v[0][1] += grad_w.x * dv.y;
+                grad_v[1][0] += grad_w.y * dv.x;
+                grad_v[1][1] += grad_w.y * dv.y;
+            }
+        }
+
+        // shear rate from the strain rate tensor, sqrt(2 D:D)
+        let d_xy = 0.5 * (grad_v[0][1] + grad_v[1][0]);
+        self.particles.shear_rate[index] = (2.0 * (grad_v[0][0] * grad_v[0][0]
+            + grad_v[1][1] * grad_v[1][1]
+            + 2.0 * d_xy * d_xy))
+            .sqrt();
+        // z component of the curl
+        self.particles.vorticity[index] = grad_v[0][1] - grad_v[1][0];
+    }
+
+    pub fn forces(&mut self, index: usize) {
+        let ps = &self.particles;
+        if ps.fixed[index] {
+            return;
+        }
+        let pos = ps.pos[index];
+        let vel = ps.vel[index];
+        let rho = ps.rho[index];
+        let press = ps.p[index];
+        let t = ps.t[index];
+        let vorticity = ps.vorticity[index];
+
+        let mu = self.phases[ps.phase[index]].viscosity.viscosity(ps.shear_rate[index]);
 
         let mut fpress = Vector2{ x: 0.0, y: 0.0 };
         let mut fvisc = Vector2{ x: 0.0, y: 0.0 };
+        let mut fart = Vector2{ x: 0.0, y: 0.0 };
+        let mut heat = 0.0;
+        let mut xsph = Vector2{ x: 0.0, y: 0.0 };
+        let mut grad_vorticity = Vector2{ x: 0.0, y: 0.0 };
 
-        for &pi in &self.particles {
-            if pi.pos == p.pos {
+        for &j in &self.neighbors[index] {
+            if ps.pos[j] == pos {
                 continue;
             }
 
-            let rij = pi.pos - p.pos;
+            let rij = self.delta(pos, ps.pos[j]);
             let r = rij.magnitude();
 
-            if r < (R*2.0) {
-                fpress += -rij.normalize() * p.m * (p.p + pi.p)/(2.0 * pi.rho) 
-                    * SPIKY_GRAD * ((2.0*R) - r) * ((2.0*R) - r);
-                fvisc += VISC * p.m * (p.vel - pi.vel)/pi.rho * VISC_LAP * ((2.0*R) - r);
+            if r < H {
+                let (m_j, rho_j) = (ps.m[j], ps.rho[j]);
+                let mu_j = self.phases[ps.phase[j]].viscosity.viscosity(ps.shear_rate[j]);
+                let grad_w = -rij.normalize() * SPIKY_GRAD * (H - r) * (H - r);
+                // x_ij . grad W / (r^2 + eta^2) with the smoother poly6 gradient,
+                // the spiky one underestimates Laplacians by about 15%
+                let lap_w = POLY6_GRAD * (H2 - r * r) * (H2 - r * r) * r * r / (r * r + 0.01 * H2);
+                fpress += -grad_w * m_j * (press + ps.p[j])/(2.0 * rho_j);
+                // Morris et al. viscosity
+                fvisc += (mu + mu_j) * m_j * (vel - ps.vel[j])/rho_j * lap_w;
+
+                if let Some(av) = self.params.artificial_viscosity {
+                    fart += -rho * m_j * monaghan(&av, vel - ps.vel[j], rho, rho_j, rij, r) * grad_w;
+                }
+
+                if let Some(thermal) = &self.params.thermal {
+                    // Brookshaw, walls outside the regions pass no heat
+                    if !ps.fixed[j] || thermal.regions.iter().any(|r| r.contains(ps.pos[j])) {
+                        heat += 2.0 * thermal.diffusivity * m_j / rho_j * (t - ps.t[j]) * lap_w;
+                    }
+                }
+
+                if let Some(eps) = self.params.xsph {
+                    let w = POLY6 * (H2 - r * r) * (H2 - r * r) * (H2 - r * r);
+                    xsph += eps * m_j * (ps.vel[j] - vel) / (0.5 * (rho + rho_j)) * w;
+                }
+
+                if self.params.vorticity_confinement.is_some() {
+                    grad_vorticity += m_j / rho_j * (ps.vorticity[j].abs() - vorticity.abs()) * grad_w;
+                }
             }
-            let fgrav = G * p.rho;
-            p.f = fpress + fvisc + fgrav;
+        }
+        // Fedkiw et al. vorticity confinement, pushes particles around the
+        // nearby maximum of |vorticity|
+        let mut fconf = Vector2{ x: 0.0, y: 0.0 };
+        if let Some(eps) = self.params.vorticity_confinement {
+            if grad_vorticity.magnitude2() > 0.0 {
+                let n = grad_vorticity.normalize();
+                fconf = rho * eps * Vector2::new(n.y * vorticity, -n.x * vorticity);
+            }
+        }
+        let mut fgrav = self.params.gravity * rho;
+        if let Some(thermal) = &self.params.thermal {
+            fgrav *= 1.0 - thermal.expansion * (t - thermal.reference);
+        }
+        self.particles.f[index] = fpress + fvisc + fart + fconf + fgrav;
+        self.particles.heat[index] = heat;
+        self.particles.xsph[index] = xsph;
+    }
+
+    pub fn integrate(&mut self, index: usize) {
+        let mut p = self.particles.get(index);
+        if p.fixed {
+            // walls stay put, but heated and cooled ones take the region
+            // temperature
+            if let Some(thermal) = &self.params.thermal {
+                if let Some(region) = thermal.regions.iter().find(|r| r.contains(p.pos)) {
+                    self.particles.t[index] = region.temperature;
+                }
+            }
+            return;
         }
 
         p.vel += DT*p.f/p.rho;
-        p.pos += DT*p.vel;
+        p.pos += DT*(p.vel + p.xsph);
 
-        
-        if p.pos.x - R < 0.0 {
+        if let Some(thermal) = &self.params.thermal {
+            p.t += DT * p.heat;
+            if let Some(region) = thermal.regions.iter().find(|r| r.contains(p.pos)) {
+                p.t = region.temperature;
+            }
+        }
+
+        if let Some(io) = self.params.inflow_outflow {
+            if p.pos.x > io.outflow {
+                p.pos.x -= io.outflow - io.inflow;
+                if let Some(v) = io.velocity {
+                    p.vel = v;
+                }
+            }
+        }
+
+        let domain = self.params.domain;
+        if domain.periodic_x {
+            p.pos.x = p.pos.x.rem_euclid(domain.width);
+        } else if p.pos.x - R < 0.0 {
             p.vel.x *= BOUND_DAMPING;
             p.pos.x = R;
-        }
-        if p.pos.x + R > VIEW_WIDTH {
+        } else if p.pos.x + R > domain.width {
             p.vel.x *= BOUND_DAMPING;
-            p.pos.x = VIEW_WIDTH - R;
+            p.pos.x = domain.width - R;
         }
-        if p.pos.y - R < 0.0 {
+        if domain.periodic_y {
+            p.pos.y = p.pos.y.rem_euclid(domain.height);
+        } else if p.pos.y - R < 0.0 {
             p.vel.y *= BOUND_DAMPING;
             p.pos.y = R;
-        }
-        if p.pos.y + R > VIEW_HEIGHT {
+        } else if p.pos.y + R > domain.height {
             p.vel.y *= BOUND_DAMPING;
-            p.pos.y = VIEW_HEIGHT - R;
+            p.pos.y = domain.height - R;
         }
 
-        self.particles[index] = p;
+        self.particles.pos[index] = p.pos;
+        self.particles.vel[index] = p.vel;
+        self.particles.t[index] = p.t;
     }
-    pub fn timestep(&mut self) {
+    pub fn timestep(&mut self) -> Result<(), StepError> {
+        if let Some(k) = self.params.reorder_every {
+            if self.steps % k == 0 {
+                self.reorder();
+            }
+        }
+        self.steps += 1;
+        self.find_neighbors();
+        for p in 0..self.particles.len() {
+            self.density(p);
+        }
+        for p in 0..self.particles.len() {
+            self.surface(p);
+            self.shear(p);
+        }
+        // all forces are evaluated before anything moves
+        for p in 0..self.particles.len() {
+            self.forces(p);
+        }
+        finite::check(self, &[Quantity::Density, Quantity::Pressure, Quantity::Force], &[])?;
+        let old_pos = match self.params.on_non_finite {
+            NonFinitePolicy::Clamp => self.particles.pos.clone(),
+            _ => vec![],
+        };
         for p in 0..self.particles.len() {
             self.integrate(p);
         }
+        finite::check(self, &[Quantity::Velocity, Quantity::Position, Quantity::Temperature], &old_pos)?;
+
+        if !self.sinks.is_empty() {
+            let sinks = &self.sinks;
+            self.particles.retain(|p| !sinks.iter().any(|s| s.contains(p.pos)));
+        }
+
+        for emitter in self.emitters.iter_mut() {
+            for pos in emitter.emit(DT, SPACING) {
+                let mut p = Particle::new(pos.x, pos.y);
+                p.vel = emitter.velocity;
+                p.phase = emitter.phase;
+                self.particles.push(p);
+            }
+        }
+
+        let time = self.time();
+        self.tracers.record(time, &self.particles);
+        self.record_probes();
+
+        if self.stats_csv.is_some() || log::log_enabled!(log::Level::Debug) {
+            let stats = self.stats();
+            log::debug!("{}", stats);
+            if let Some(w) = &mut self.stats_csv {
+                if let Err(e) = stats.write_csv_row(w) {
+                    log::error!("stats csv stopped: {}", e);
+                    self.stats_csv = None;
+                }
+            }
+        }
+        Ok(())
     }
+
+    // centered on the domain, which is scaled uniformly so its longer side
+    // spans [-1, 1]
     pub fn instances(&self) -> Vec<Instance> {
+        let domain = self.params.domain;
+        let s = 2.0 / domain.width.max(domain.height);
         let instances = self
             .particles
             .iter()
             .map(|p| Instance {
                 position: cgmath::Vector3 {
-                    x: ((p.pos.x - 0.5) * 2.0),
-                    y: ((p.pos.y - 0.5) * 2.0),
+                    x: ((p.pos.x - 0.5 * domain.width) * s) as f32,
+                    y: ((p.pos.y - 0.5 * domain.height) * s) as f32,
                     z: 0.0,
                 },
                 rotation: cgmath::Quaternion::from_axis_angle(
                     cgmath::Vector3::unit_z(),
                     cgmath::Deg(0.0),
                 ),
-                scale: 2.0 * cgmath::Vector2{x: R, y: R},
+                scale: cgmath::Vector2{x: (R * s) as f32, y: (R * s) as f32},
+                surface: p.surface,
+                normal: cgmath::Vector2{x: p.normal.x as f32, y: p.normal.y as f32},
+                scalar: 0.0,
             })
             .collect::<Vec<_>>();
         instances
     }
 }
 
+impl finite::Checked for Sph {
+    type Vector = Vector2<Real>;
+
+    fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn policy(&self) -> NonFinitePolicy {
+        self.params.on_non_finite
+    }
+
+    fn reference_temperature(&self) -> Real {
+        self.params.thermal.as_ref().map_or(0.0, |t| t.reference)
+    }
+
+    fn count(&self) -> usize {
+        self.particles.len()
+    }
+
+    fn state(&mut self, index: usize) -> finite::State<'_, Vector2<Real>> {
+        let ps = &mut self.particles;
+        finite::State {
+            id: ps.id[index],
+            rho: &mut ps.rho[index],
+            p: &mut ps.p[index],
+            f: &mut ps.f[index],
+            vel: &mut ps.vel[index],
+            pos: &mut ps.pos[index],
+            t: Some(&mut ps.t[index]),
+        }
+    }
+
+    fn keep(&mut self, kept: &[usize]) {
+        self.particles.permute(kept);
+    }
+}
+
+impl Simulation for Sph {
+    fn timestep(&mut self) -> Result<(), StepError> {
+        Sph::timestep(self)
+    }
+    fn instances(&self) -> Vec<Instance> {
+        Sph::instances(self)
+    }
+    fn scalars(&self, field: Field) -> Vec<f32> {
+        self.field(field).into_iter().map(|v| v as f32).collect()
+    }
+    fn surface_mesh(&self) -> Vec<Vertex> {
+        let domain = self.params.domain;
+        let s = 2.0 / domain.width.max(domain.height);
+        let contours = self.contours(H / 4.0);
+        contours
+            .triangles
+            .iter()
+            .flatten()
+            .map(|p| Vertex {
+                position: [((p.x - 0.5 * domain.width) * s) as f32, ((p.y - 0.5 * domain.height) * s) as f32],
+            })
+            .collect()
+    }
+}
+
+// Monaghan's Pi_ij, zero for particles moving apart
+fn monaghan(av: &ArtificialViscosity, vij: Vector2<Real>, rho_i: Real, rho_j: Real, rij: Vector2<Real>, r: Real) -> Real {
+    let vr = vij.dot(-rij);
+    if vr >= 0.0 {
+        return 0.0;
+    }
+    let c = GAS_CONST.sqrt();
+    let mu = H * vr / (r * r + 0.01 * H2);
+    (-av.alpha * c * mu + av.beta * mu * mu) / (0.5 * (rho_i + rho_j))
+}
+
+// n by n particles SPACING apart, the first one at `origin`
+#[cfg(test)]
+pub(crate) fn block(n: usize, origin: Vector2<Real>) -> Particles {
+    (0..n)
+        .flat_map(|i| (0..n).map(move |j| origin + Vector2::new(i as Real, j as Real) * SPACING))
+        .map(|pos| Particle::new(pos.x, pos.y))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::sampling::SampleGrid;
+    use crate::sources::{InflowOutflow, Shape};
+    use crate::thermal::{Region, Thermal};
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn solver_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Sph>();
+    }
+
+    // drops a low viscosity block and returns the rms deviation of each
+    // particle velocity from the mean velocity of its neighborhood,
+    // averaged over the run
+    fn noise(params: Params) -> Real {
+        let mut sph = Sph::new(0);
+        sph.particles = block(12, Vector2::new(0.3, 0.3));
+        sph.phases[0] = Phase::new(Newtonian { mu: 0.5 });
+        sph.params = params;
+        let mut total = 0.0;
+        for step in 0..800 {
+            sph.timestep().unwrap();
+            if step % 20 == 0 {
+                let mut sum = 0.0;
+                for p in sph.particles.iter() {
+                    let near = sph.particles.iter().filter(|q| (q.pos - p.pos).magnitude2() < H2);
+                    let (n, v) = near.fold((0.0, Vector2::new(0.0, 0.0)), |(n, v), q| (n + 1.0, v + q.vel));
+                    sum += (p.vel - v / n).magnitude2();
+                }
+                total += (sum / sph.particles.len() as Real).sqrt();
+            }
+        }
+        total / 40.0
+    }
+
+    #[test]
+    fn artificial_viscosity_reduces_noise() {
+        let plain = noise(Params::default());
+        let damped = noise(Params {
+            artificial_viscosity: Some(ArtificialViscosity::default()),
+            ..Params::default()
+        });
+        assert!(damped < 0.5 * plain, "{} vs {}", damped, plain);
+    }
+
+    #[test]
+    fn xsph_reduces_noise() {
+        let plain = noise(Params::default());
+        let smoothed = noise(Params {
+            xsph: Some(0.5),
+            ..Params::default()
+        });
+        assert!(smoothed < 0.9 * plain, "{} vs {}", smoothed, plain);
+    }
+
+    // Run by ci/precision-drift.sh, which builds the solver in both
+    // precisions. Each run writes its final positions to SPH_DRIFT_OUT, the
+    // second one also compares them with the first one's in SPH_DRIFT_REF.
+    #[test]
+    #[ignore]
+    fn precision_drift() {
+        let mut sph = Sph::new(0);
+        sph.particles = block(10, Vector2::new(0.3, 0.3));
+        for _ in 0..400 {
+            sph.timestep().unwrap();
+        }
+
+        let out = std::env::var("SPH_DRIFT_OUT").expect("SPH_DRIFT_OUT is set by ci/precision-drift.sh");
+        let lines: Vec<String> = sph.particles.iter().map(|p| format!("{} {}", p.pos.x, p.pos.y)).collect();
+        std::fs::write(out, lines.join("\n")).unwrap();
+
+        let reference = match std::env::var("SPH_DRIFT_REF") {
+            Ok(path) => std::fs::read_to_string(path).unwrap(),
+            Err(_) => return,
+        };
+        let reference: Vec<&str> = reference.lines().collect();
+        assert_eq!(reference.len(), sph.particles.len());
+        let drift: Vec<f64> = reference
+            .iter()
+            .zip(sph.particles.iter())
+            .map(|(line, p)| {
+                let xy: Vec<f64> = line.split(' ').map(|v| v.parse().unwrap()).collect();
+                ((xy[0] - p.pos.x as f64).powi(2) + (xy[1] - p.pos.y as f64).powi(2)).sqrt()
+            })
+            .collect();
+        let max = drift.iter().cloned().fold(0.0, f64::max);
+        let rms = (drift.iter().map(|d| d * d).sum::<f64>() / drift.len() as f64).sqrt();
+        println!("f32 vs f64 after 400 steps: max drift {:e}, rms drift {:e}", max, rms);
+        assert!(rms < 0.01 * SPACING as f64, "{}", rms);
+    }
+
+    #[test]
+    fn reordering_keeps_ids() {
+        let block = || {
+            let mut sph = Sph::new(0);
+            sph.particles = block(10, Vector2::new(0.3, 0.3));
+            sph
+        };
+        let mut plain = block();
+        let mut sorted = block();
+        sorted.params.reorder_every = Some(10);
+        for _ in 0..200 {
+            plain.timestep().unwrap();
+            sorted.timestep().unwrap();
+        }
+        assert!((0..sorted.particles.len()).any(|i| sorted.particles.id(i) != i));
+        for i in 0..sorted.particles.len() {
+            let p = sorted.particles.get(i);
+            let q = plain.particles.get(p.id);
+            assert!((p.pos - q.pos).magnitude() < 0.01 * SPACING, "{:?} {:?}", p.pos, q.pos);
+        }
+    }
+
+    #[test]
+    fn non_finite_policies() {
+        let broken = |policy| {
+            let mut sph = Sph::new(4);
+            sph.particles.push(Particle::new(0.9, 0.9));
+            sph.particles.m[16] = 0.0;
+            sph.params.on_non_finite = policy;
+            sph
+        };
+        finite::check_policies(broken, 16);
+    }
+
+    // fills the bottom of the view with a layer of fluid
+    fn layer(rows: usize) -> Sph {
+        let mut sph = Sph::new(0);
+        let cols = (VIEW_WIDTH / SPACING) as usize;
+        sph.particles = (0..cols)
+            .flat_map(|i| (0..rows).map(move |j| Particle::new((i as Real + 0.5) * SPACING, (j as Real + 0.5) * SPACING)))
+            .collect();
+        sph
+    }
+
+    #[test]
+    fn conduction_equalizes_temperature() {
+        let mut sph = layer(3);
+        sph.params.thermal = Some(Thermal { diffusivity: 0.2, ..Thermal::default() });
+        for i in 0..sph.particles.len() {
+            sph.particles.t[i] = if sph.particles.pos[i].x < 0.5 { 1.0 } else { 0.0 };
+        }
+        let mean = |sph: &Sph, left: bool| {
+            let t = sph.particles.iter().filter(|p| (p.pos.x < 0.5) == left).map(|p| p.t);
+            let (n, sum) = t.fold((0.0, 0.0), |(n, sum), t| (n + 1.0, sum + t));
+            sum / n
+        };
+        for _ in 0..500 {
+            sph.timestep().unwrap();
+        }
+
+        let total = sph.field(Field::Temperature).iter().sum::<Real>() / sph.particles.len() as Real;
+        assert!((total - 0.5).abs() < 0.02, "{}", total);
+        assert!(mean(&sph, true) < 0.9 && mean(&sph, false) > 0.1);
+    }
+
+    #[test]
+    fn heated_fluid_rises() {
+        let height = |expansion: Real| {
+            let mut sph = layer(8);
+            sph.phases[0] = Phase::new(Newtonian { mu: 0.5 });
+            sph.params.thermal = Some(Thermal { expansion, diffusivity: 0.0, ..Thermal::default() });
+            for i in 0..sph.particles.len() {
+                let pos = sph.particles.pos[i];
+                if (pos.x - 0.5).abs() < 0.1 && (pos.y - 4.0 * SPACING).abs() < SPACING {
+                    sph.particles.t[i] = 1.0;
+                }
+            }
+            for _ in 0..800 {
+                sph.timestep().unwrap();
+            }
+            let hot = sph.particles.iter().filter(|p| p.t > 0.5);
+            let (n, y) = hot.fold((0.0, 0.0), |(n, y), p| (n + 1.0, y + p.pos.y));
+            y / n
+        };
+        assert!(height(0.5) > height(0.0) + SPACING);
+    }
+
+    // Differentially heated square cavity of de Vahl Davis: hot wall on
+    // the left, cold on the right, adiabatic floor and lid, Pr = 0.71.
+    // Gravity is reduced so that the hydrostatic compression stays small
+    // next to the weakly compressible pressure, and the temperature
+    // difference makes up for it with expansion * dT = 1, which only
+    // enters the body force. Returns the solver and the diffusivity.
+    fn cavity(n: usize, rayleigh: Real) -> (Sph, Real) {
+        let walls = 3;
+        let side = n as Real * SPACING;
+        let outer = (n + 2 * walls) as Real * SPACING;
+        let (prandtl, expansion, g) = (0.71, 1.0, -GRAVITY / 4.0);
+        let alpha = (g * expansion * side.powi(3) / (prandtl * rayleigh)).sqrt();
+        let mut sph = Sph::new(0);
+        sph.params.domain = Domain { width: outer, height: outer, ..Domain::default() };
+        sph.params.gravity = Vector2::new(0.0, -g);
+        sph.phases[0] = Phase::new(Newtonian { mu: prandtl * alpha * REST_DENS });
+        let (lo, hi) = (walls as Real * SPACING, (n + walls) as Real * SPACING);
+        let wall = |min_x, max_x, temperature| Region {
+            min: Vector2::new(min_x, lo),
+            max: Vector2::new(max_x, hi),
+            temperature,
+        };
+        sph.params.thermal = Some(Thermal {
+            diffusivity: alpha,
+            expansion,
+            reference: 0.5,
+            regions: vec![wall(0.0, lo, 1.0), wall(hi, outer, 0.0)],
+        });
+        for i in 0..n + 2 * walls {
+            for j in 0..n + 2 * walls {
+                sph.particles.push(Particle::new((i as Real + 0.5) * SPACING, (j as Real + 0.5) * SPACING));
+                let last = sph.particles.len() - 1;
+                sph.particles.t[last] = 0.5;
+                if i < walls || j < walls || i >= n + walls || j >= n + walls {
+                    sph.fix(last);
+                }
+            }
+        }
+        (sph, alpha)
+    }
+
+    // volume averaged horizontal heat flux u T - alpha dT/dx of the fluid,
+    // over the conductive flux alpha dT / L
+    fn nusselt(sph: &Sph, alpha: Real, side: Real) -> Real {
+        let ps = &sph.particles;
+        let thermal = sph.params.thermal.as_ref().unwrap();
+        let conducts = |j: usize| !ps.fixed[j] || thermal.regions.iter().any(|r| r.contains(ps.pos[j]));
+        let (mut flux, mut volume) = (0.0, 0.0);
+        for i in (0..ps.len()).filter(|&i| !ps.fixed[i]) {
+            // normalized so that linear fields come out exact next to walls
+            let (mut dt, mut dx) = (0.0, 0.0);
+            for &j in sph.neighbors[i].iter().filter(|&&j| j != i && conducts(j)) {
+                let rij = ps.pos[j] - ps.pos[i];
+                let r = rij.magnitude();
+                let w_x = ps.m[j] / ps.rho[j] * (H - r) * (H - r) * rij.x / r;
+                dt += w_x * (ps.t[j] - ps.t[i]);
+                dx += w_x * rij.x;
+            }
+            let v = ps.m[i] / ps.rho[i];
+            flux += v * (ps.vel[i].x * (ps.t[i] - thermal.reference) - alpha * dt / dx);
+            volume += v;
+        }
+        flux / volume * side / alpha
+    }
+
+    // slow, run with --ignored
+    #[test]
+    #[ignore]
+    fn heated_cavity_nusselt() {
+        let n = 20;
+        let (mut sph, alpha) = cavity(n, 1e3);
+        // steady after about 4000 steps, the flux still flickers a little
+        // so it is averaged over the last ones
+        let mut nu = 0.0;
+        for step in 0..12000 {
+            sph.timestep().unwrap();
+            if step >= 8000 && step % 100 == 0 {
+                nu += nusselt(&sph, alpha, n as Real * SPACING) / 40.0;
+            }
+        }
+        // de Vahl Davis (1983) has 1.118 at Ra = 1e3, the jump in
+        // temperature at the walls keeps 20 particles across about 7% low
+        assert!((nu - 1.118).abs() < 0.1 * 1.118, "Nu = {}", nu);
+    }
+
+    #[test]
+    fn emitted_particles_reach_sink() {
+        let mut sph = Sph::new(0);
+        let nozzle = Shape::Nozzle { center: Vector2::new(0.5, 0.8), width: 0.1 };
+        // 5 slots can release up to 200 particles per second at this speed
+        sph.emitters.push(Emitter::new(nozzle, 150.0, Vector2::new(0.0, -1.0)));
+        sph.sinks.push(Sink { min: Vector2::new(0.0, 0.0), max: Vector2::new(1.0, 0.3) });
+        for _ in 0..250 {
+            sph.timestep().unwrap();
+        }
+        // 150 per second over 0.2s, none has fallen far enough yet
+        assert!((sph.particles.len() as i32 - 30).abs() <= 1);
+
+        sph.emitters.clear();
+        for _ in 0..1000 {
+            sph.timestep().unwrap();
+        }
+        assert!(sph.particles.is_empty());
+    }
+
+    #[test]
+    fn outflow_recycles_to_inflow() {
+        let mut sph = Sph::new(0);
+        sph.params.gravity = Vector2::new(0.0, 0.0);
+        let velocity = Vector2::new(0.5, 0.0);
+        sph.params.inflow_outflow = Some(InflowOutflow { inflow: 0.2, outflow: 0.8, velocity: Some(velocity) });
+        let mut p = Particle::new(0.8 - 0.001, 0.5);
+        p.vel = Vector2::new(2.0, 0.0);
+        sph.particles.push(p);
+        sph.timestep().unwrap();
+        let p = sph.particles.get(0);
+        assert!(p.pos.x > 0.2 && p.pos.x < 0.2 + SPACING, "{:?}", p.pos);
+        assert_eq!(p.vel, velocity);
+    }
+
+    // gravity along the channel drives the fluid through the planes
+    fn channel(rows: usize) -> Sph {
+        let (inflow, outflow) = (0.2, 0.8);
+        let mut sph = Sph::new(0);
+        sph.params.gravity = Vector2::new(2.0, GRAVITY);
+        sph.params.inflow_outflow = Some(InflowOutflow { inflow, outflow, velocity: None });
+        let cols = ((outflow - inflow) / SPACING).round() as usize;
+        sph.particles = (0..cols)
+            .flat_map(|i| (0..rows).map(move |j| Particle::new(inflow + (i as Real + 0.5) * SPACING, (j as Real + 0.5) * SPACING)))
+            .collect();
+        sph
+    }
+
+    #[test]
+    fn channel_flow_through_inflow() {
+        let mut sph = channel(8);
+        let count = sph.particles.len();
+        let mut recycled = 0;
+        for _ in 0..1000 {
+            let before: Vec<Real> = sph.particles.pos.iter().map(|p| p.x).collect();
+            sph.timestep().unwrap();
+            recycled += before.iter().zip(&sph.particles.pos).filter(|(x, p)| p.x < **x - 0.3).count();
+            assert_eq!(sph.particles.len(), count);
+            // away from the floor and the free surface, the fluid next to
+            // the inflow sees the fluid about to leave and keeps its density
+            for p in sph.particles.iter().filter(|p| p.pos.x < 0.2 + H && p.pos.y > H && p.pos.y < 6.0 * SPACING) {
+                assert!((p.rho - REST_DENS).abs() < 0.1 * REST_DENS, "{:?}", p);
+            }
+        }
+        assert!(recycled > 0);
+        assert!(sph.particles.pos.iter().all(|p| p.x >= 0.2 && p.x <= 0.8));
+    }
+
+    // Taylor-Green vortex in a periodic box, vorticity is
+    // 2 u k sin(kx) sin(ky)
+    fn taylor_green(confinement: Option<Real>) -> Sph {
+        let (n, u) = (20, 0.2);
+        let width = n as Real * SPACING;
+        let k = 2.0 * PI / width;
+        let mut sph = Sph::new(0);
+        sph.params.domain = Domain { width, height: width, periodic_x: true, periodic_y: true };
+        sph.params.gravity = Vector2::new(0.0, 0.0);
+        sph.params.vorticity_confinement = confinement;
+        sph.phases[0] = Phase::new(Newtonian { mu: 0.5 });
+        for i in 0..n {
+            for j in 0..n {
+                let (x, y) = ((i as Real + 0.5) * SPACING, (j as Real + 0.5) * SPACING);
+                let mut p = Particle::new(x, y);
+                p.vel = u * Vector2::new((k * x).sin() * (k * y).cos(), -(k * x).cos() * (k * y).sin());
+                sph.particles.push(p);
+            }
+        }
+        sph
+    }
+
+    #[test]
+    fn vorticity_of_taylor_green_vortex() {
+        let mut sph = taylor_green(None);
+        sph.timestep().unwrap();
+        let k = 2.0 * PI / sph.params.domain.width;
+        let vorticity = sph.field(Field::Vorticity);
+        for (p, w) in sph.particles.iter().zip(&vorticity) {
+            let expected = 2.0 * 0.2 * k * (k * p.pos.x).sin() * (k * p.pos.y).sin();
+            // the spiky gradient underestimates at this resolution
+            assert!((w - expected).abs() < 0.2 * 2.0 * 0.2 * k, "{} vs {}", w, expected);
+        }
+    }
+
+    #[test]
+    fn vorticity_confinement_keeps_vortex_spinning() {
+        let energy = |confinement| {
+            let mut sph = taylor_green(confinement);
+            sph.timestep().unwrap();
+            let initial = sph.stats().kinetic_energy;
+            for _ in 0..500 {
+                sph.timestep().unwrap();
+            }
+            (initial, sph.stats().kinetic_energy)
+        };
+        let (initial, plain) = energy(None);
+        let (_, confined) = energy(Some(0.05));
+        // confinement only puts back some of what viscosity takes
+        assert!(confined > 2.0 * plain && confined < initial, "{} vs {}", confined, plain);
+    }
+
+    #[test]
+    fn free_surface_of_a_block() {
+        let mut sph = Sph::new(0);
+        sph.particles = block(12, Vector2::new(0.3, 0.3));
+        sph.particles.push(Particle::new(0.8, 0.8));
+        sph.timestep().unwrap();
+        let (lo, hi) = (0.3 + 0.5 * SPACING, 0.3 + 10.5 * SPACING);
+        for i in 0..sph.particles.len() - 1 {
+            let p = sph.particles.get(i);
+            let inside = p.pos.x > lo && p.pos.x < hi && p.pos.y > lo && p.pos.y < hi;
+            assert_eq!(sph.is_surface(i), !inside, "{:?}", p.pos);
+            if !inside {
+                // outward, away from the block center
+                let out = p.pos - Vector2::new(0.3 + 5.5 * SPACING, 0.3 + 5.5 * SPACING);
+                assert!(sph.normal(i).dot(out) > 0.0 && (sph.normal(i).magnitude() - 1.0).abs() < 1e-4);
+            }
+        }
+        // too few neighbors for a gradient
+        let lone = sph.particles.len() - 1;
+        assert!(sph.is_surface(lone) && sph.normal(lone) == Vector2::new(0.0, 0.0));
+        assert_eq!(sph.field(Field::Surface).iter().sum::<Real>() as usize, 4 * 11 + 1);
+
+        // the left edge points along -x, sampled away from the corners
+        let grid = SampleGrid { origin: Vector2::new(0.3, lo + H), spacing: Vector2::new(SPACING, SPACING), nx: 1, ny: 4 };
+        let left = sph.sample(&grid, Field::NormalX);
+        assert!(left.values.iter().all(|&n| n < -0.5), "{:?}", left.values);
+        assert!(sph.sample(&grid, Field::NormalY).values.iter().all(|n| n.abs() < 0.1));
+    }
+
+    #[test]
+    fn poiseuille_flow() {
+        // periodic channel along x, three rows of fixed particles per wall
+        let (cols, rows, walls) = (10, 10, 3);
+        let force = 5.0;
+        let mut sph = Sph::new(0);
+        sph.params.domain = Domain {
+            width: cols as Real * SPACING,
+            height: (rows + 2 * walls) as Real * SPACING,
+            periodic_x: true,
+            periodic_y: false,
+        };
+        sph.params.gravity = Vector2::new(force, 0.0);
+        for i in 0..cols {
+            for j in 0..rows + 2 * walls {
+                sph.particles.push(Particle::new((i as Real + 0.5) * SPACING, (j as Real + 0.5) * SPACING));
+                if j < walls || j >= rows + walls {
+                    let last = sph.particles.len() - 1;
+                    sph.fix(last);
+                }
+            }
+        }
+        for _ in 0..2000 {
+            sph.timestep().unwrap();
+        }
+
+        // u(y) = f / (2 nu) * y * (d - y), with the no-slip wall sitting on
+        // the innermost row of fixed particles
+        let nu = VISC / REST_DENS;
+        let d = (rows + 1) as Real * SPACING;
+        let u_max = force * d * d / (8.0 * nu);
+        for p in sph.particles.iter().filter(|p| !p.fixed) {
+            let y = p.pos.y - (walls as Real - 0.5) * SPACING;
+            let u = force / (2.0 * nu) * y * (d - y);
+            assert!((p.vel.x - u).abs() < 0.05 * u_max, "u({}) = {}, expected {}", y, p.vel.x, u);
+        }
+    }
 }
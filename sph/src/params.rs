@@ -0,0 +1,111 @@
+use crate::sources::InflowOutflow;
+use crate::thermal::Thermal;
+use crate::{Real, G, VIEW_HEIGHT, VIEW_WIDTH};
+use cgmath::Vector2;
+
+// Monaghan artificial viscosity, only acts between approaching particles.
+// alpha scales the linear (bulk) term and beta the quadratic term that
+// stops particle interpenetration in strong compressions.
+#[derive(Copy, Clone, Debug)]
+pub struct ArtificialViscosity {
+    pub alpha: Real,
+    pub beta: Real,
+}
+
+impl Default for ArtificialViscosity {
+    fn default() -> Self {
+        ArtificialViscosity {
+            alpha: 0.1,
+            beta: 0.0,
+        }
+    }
+}
+
+// Simulated box starting at 0,0. A periodic axis wraps positions and
+// neighbor queries instead of bouncing particles off the walls.
+#[derive(Copy, Clone, Debug)]
+pub struct Domain {
+    pub width: Real,
+    pub height: Real,
+    pub periodic_x: bool,
+    pub periodic_y: bool,
+}
+
+impl Default for Domain {
+    fn default() -> Self {
+        Domain {
+            width: VIEW_WIDTH,
+            height: VIEW_HEIGHT,
+            periodic_x: false,
+            periodic_y: false,
+        }
+    }
+}
+
+// What a step does with a particle whose state went NaN or infinite.
+// Clamp puts back rest density, zero pressure, force and velocity, and the
+// position from the start of the step.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum NonFinitePolicy {
+    Abort,
+    Clamp,
+    Remove,
+}
+
+// A particle is on the free surface when its color field gradient is
+// larger than `gradient` / H, or when it has fewer than `min_neighbors`
+// neighbors, itself included.
+#[derive(Copy, Clone, Debug)]
+pub struct FreeSurface {
+    // threshold on |grad c| H, which is 0 deep in the fluid, up to about
+    // 0.35 one row below a flat surface and about 0.87 on it, so the
+    // default 0.5 sits between the two
+    pub gradient: Real,
+    pub min_neighbors: usize,
+}
+
+impl Default for FreeSurface {
+    fn default() -> Self {
+        FreeSurface {
+            gradient: 0.5,
+            min_neighbors: 5,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Params {
+    pub domain: Domain,
+    pub gravity: Vector2<Real>,
+    pub artificial_viscosity: Option<ArtificialViscosity>,
+    // XSPH epsilon, particles are moved with a velocity blended towards
+    // the neighbor average, usually between 0.0 and 0.5
+    pub xsph: Option<Real>,
+    // vorticity confinement epsilon, scales the acceleration that keeps
+    // small swirls spinning
+    pub vorticity_confinement: Option<Real>,
+    pub thermal: Option<Thermal>,
+    pub inflow_outflow: Option<InflowOutflow>,
+    // sort particle storage by Morton code every K steps, particle indices
+    // change but ids do not
+    pub reorder_every: Option<usize>,
+    pub on_non_finite: NonFinitePolicy,
+    pub free_surface: FreeSurface,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            domain: Domain::default(),
+            gravity: G,
+            artificial_viscosity: None,
+            xsph: None,
+            vorticity_confinement: None,
+            thermal: None,
+            inflow_outflow: None,
+            reorder_every: None,
+            on_non_finite: NonFinitePolicy::Abort,
+            free_surface: FreeSurface::default(),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{Particle, Real};
+use cgmath::Vector2;
+
+// Structure of arrays, one vector per particle attribute so the neighbor
+// loops only stream through the attributes they read. Storage may be
+// reordered, `id` follows a particle for its whole life.
+#[derive(Clone, Debug, Default)]
+pub struct Particles {
+    pub(crate) id: Vec<usize>,
+    pub(crate) pos: Vec<Vector2<Real>>,
+    pub(crate) vel: Vec<Vector2<Real>>,
+    pub(crate) m: Vec<Real>,
+    pub(crate) rho: Vec<Real>,
+    pub(crate) p: Vec<Real>,
+    pub(crate) f: Vec<Vector2<Real>>,
+    pub(crate) phase: Vec<usize>,
+    pub(crate) shear_rate: Vec<Real>,
+    pub(crate) vorticity: Vec<Real>,
+    pub(crate) surface: Vec<bool>,
+    pub(crate) normal: Vec<Vector2<Real>>,
+    pub(crate) t: Vec<Real>,
+    pub(crate) heat: Vec<Real>,
+    pub(crate) xsph: Vec<Vector2<Real>>,
+    pub(crate) fixed: Vec<bool>,
+    // neighbors within H in the last step, itself included, 0 for a
+    // particle added since
+    pub(crate) neighbor_count: Vec<usize>,
+    next_id: usize,
+}
+
+impl Particles {
+    pub fn len(&self) -> usize {
+        self.pos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos.is_empty()
+    }
+
+    // adds a new particle, which gets the next free id
+    pub fn push(&mut self, p: Particle) {
+        self.id.push(self.next_id);
+        self.next_id += 1;
+        self.pos.push(p.pos);
+        self.vel.push(p.vel);
+        self.m.push(p.m);
+        self.rho.push(p.rho);
+        self.p.push(p.p);
+        self.f.push(p.f);
+        self.phase.push(p.phase);
+        self.shear_rate.push(p.shear_rate);
+        self.vorticity.push(p.vorticity);
+        self.surface.push(p.surface);
+        self.normal.push(p.normal);
+        self.t.push(p.t);
+        self.heat.push(p.heat);
+        self.xsph.push(p.xsph);
+        self.fixed.push(p.fixed);
+        self.neighbor_count.push(0);
+    }
+
+    pub fn get(&self, index: usize) -> Particle {
+        Particle {
+            id: self.id[index],
+            pos: self.pos[index],
+            vel: self.vel[index],
+            m: self.m[index],
+            rho: self.rho[index],
+            p: self.p[index],
+            f: self.f[index],
+            phase: self.phase[index],
+            shear_rate: self.shear_rate[index],
+            vorticity: self.vorticity[index],
+            surface: self.surface[index],
+            normal: self.normal[index],
+            t: self.t[index],
+            heat: self.heat[index],
+            xsph: self.xsph[index],
+            fixed: self.fixed[index],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Particle> + '_ {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+
+    pub fn id(&self, index: usize) -> usize {
+        self.id[index]
+    }
+
+    pub fn retain<F: FnMut(&Particle) -> bool>(&mut self, mut keep: F) {
+        let kept: Vec<usize> = (0..self.len()).filter(|&i| keep(&self.get(i))).collect();
+        self.permute(&kept);
+    }
+
+    // keeps the particles at `order`, in that order
+    pub(crate) fn permute(&mut self, order: &[usize]) {
+        fn pick<T: Copy>(v: &mut Vec<T>, order: &[usize]) {
+            *v = order.iter().map(|&i| v[i]).collect();
+        }
+        pick(&mut self.id, order);
+        pick(&mut self.pos, order);
+        pick(&mut self.vel, order);
+        pick(&mut self.m, order);
+        pick(&mut self.rho, order);
+        pick(&mut self.p, order);
+        pick(&mut self.f, order);
+        pick(&mut self.phase, order);
+        pick(&mut self.shear_rate, order);
+        pick(&mut self.vorticity, order);
+        pick(&mut self.surface, order);
+        pick(&mut self.normal, order);
+        pick(&mut self.t, order);
+        pick(&mut self.heat, order);
+        pick(&mut self.xsph, order);
+        pick(&mut self.fixed, order);
+        pick(&mut self.neighbor_count, order);
+    }
+}
+
+impl std::iter::FromIterator<Particle> for Particles {
+    fn from_iter<I: IntoIterator<Item = Particle>>(iter: I) -> Self {
+        let mut particles = Particles::default();
+        for p in iter {
+            particles.push(p);
+        }
+        particles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retain_keeps_attributes_together() {
+        let mut particles = (0..4).map(|i| Particle::new(i as Real, 0.0)).collect::<Particles>();
+        particles.t[2] = 5.0;
+        particles.retain(|p| p.pos.x > 1.0);
+        assert_eq!(particles.len(), 2);
+        assert_eq!(particles.get(0).pos.x, 2.0);
+        assert_eq!(particles.get(0).t, 5.0);
+        assert_eq!(particles.id(0), 2);
+        particles.push(Particle::new(0.0, 0.0));
+        assert_eq!(particles.id(2), 4);
+    }
+}
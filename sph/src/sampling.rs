@@ -0,0 +1,262 @@
+use crate::grid::Grid;
+use crate::{Field, Real, Sph, H, H2, POLY6};
+use cgmath::{InnerSpace, Vector2};
+use std::io::{self, Write};
+
+// Regular grid of sample points, point (i, j) is at
+// origin + (i * spacing.x, j * spacing.y).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SampleGrid {
+    pub origin: Vector2<Real>,
+    pub spacing: Vector2<Real>,
+    pub nx: usize,
+    pub ny: usize,
+}
+
+impl SampleGrid {
+    pub fn point(&self, i: usize, j: usize) -> Vector2<Real> {
+        self.origin + Vector2::new(i as Real * self.spacing.x, j as Real * self.spacing.y)
+    }
+}
+
+// Values sampled on a grid, row major with x fastest and `components`
+// values per point. Points with no particle within H hold NaN.
+#[derive(Clone, Debug)]
+pub struct GridField {
+    pub grid: SampleGrid,
+    pub components: usize,
+    pub values: Vec<Real>,
+}
+
+impl GridField {
+    pub fn get(&self, i: usize, j: usize) -> &[Real] {
+        let start = (j * self.grid.nx + i) * self.components;
+        &self.values[start..start + self.components]
+    }
+
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "x,y")?;
+        for c in 0..self.components {
+            write!(w, ",value_{}", c)?;
+        }
+        writeln!(w)?;
+        for j in 0..self.grid.ny {
+            for i in 0..self.grid.nx {
+                let p = self.grid.point(i, j);
+                write!(w, "{},{}", p.x, p.y)?;
+                for v in self.get(i, j) {
+                    write!(w, ",{}", v)?;
+                }
+                writeln!(w)?;
+            }
+        }
+        Ok(())
+    }
+
+    // NumPy format 1.0, shape (ny, nx) or (ny, nx, components)
+    pub fn write_npy<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let descr = if std::mem::size_of::<Real>() == 4 { "<f4" } else { "<f8" };
+        let shape = if self.components == 1 {
+            format!("({}, {})", self.grid.ny, self.grid.nx)
+        } else {
+            format!("({}, {}, {})", self.grid.ny, self.grid.nx, self.components)
+        };
+        let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+        // magic, version and length take 10 bytes, the data must start 64
+        // byte aligned and the header ends with a newline
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+        w.write_all(b"\x93NUMPY\x01\x00")?;
+        w.write_all(&(header.len() as u16).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        for v in &self.values {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // legacy VTK structured points, which ParaView reads as image data
+    pub fn write_vtk<W: Write>(&self, mut w: W, name: &str) -> io::Result<()> {
+        let g = &self.grid;
+        writeln!(w, "# vtk DataFile Version 3.0")?;
+        writeln!(w, "{}", name)?;
+        writeln!(w, "ASCII")?;
+        writeln!(w, "DATASET STRUCTURED_POINTS")?;
+        writeln!(w, "DIMENSIONS {} {} 1", g.nx, g.ny)?;
+        writeln!(w, "ORIGIN {} {} 0", g.origin.x, g.origin.y)?;
+        writeln!(w, "SPACING {} {} 1", g.spacing.x, g.spacing.y)?;
+        writeln!(w, "POINT_DATA {}", g.nx * g.ny)?;
+        writeln!(w, "SCALARS {} float {}", name, self.components)?;
+        writeln!(w, "LOOKUP_TABLE default")?;
+        for point in self.values.chunks(self.components) {
+            let line: Vec<String> = point.iter().map(|v| v.to_string()).collect();
+            writeln!(w, "{}", line.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+// Records a field at a fixed location after every step.
+#[derive(Clone, Debug)]
+pub struct Probe {
+    pub pos: Vector2<Real>,
+    pub field: Field,
+    series: Vec<(Real, Real)>,
+}
+
+impl Probe {
+    pub fn new(pos: Vector2<Real>, field: Field) -> Self {
+        Probe { pos, field, series: vec![] }
+    }
+
+    // (time, value) pairs
+    pub fn series(&self) -> &[(Real, Real)] {
+        &self.series
+    }
+
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "time,value")?;
+        for (time, value) in &self.series {
+            writeln!(w, "{},{}", time, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sph {
+    pub fn sample(&self, grid: &SampleGrid, field: Field) -> GridField {
+        self.sample_values(grid, &self.field(field), 1)
+    }
+
+    pub fn sample_velocity(&self, grid: &SampleGrid) -> GridField {
+        let mut values = Vec::with_capacity(2 * self.particles.len());
+        for v in &self.particles.vel {
+            values.push(v.x);
+            values.push(v.y);
+        }
+        self.sample_values(grid, &values, 2)
+    }
+
+    // custom per particle attribute, `components` values per particle in
+    // particle index order
+    pub fn sample_values(&self, grid: &SampleGrid, values: &[Real], components: usize) -> GridField {
+        assert_eq!(values.len(), self.particles.len() * components);
+        let search = self.search_grid();
+        let mut out = vec![0.0; grid.nx * grid.ny * components];
+        for j in 0..grid.ny {
+            for i in 0..grid.nx {
+                let start = (j * grid.nx + i) * components;
+                self.shepard(&search, grid.point(i, j), values, &mut out[start..start + components]);
+            }
+        }
+        GridField { grid: *grid, components, values: out }
+    }
+
+    pub(crate) fn record_probes(&mut self) {
+        if self.probes.is_empty() {
+            return;
+        }
+        let search = self.search_grid();
+        let time = self.time();
+        let mut probes = std::mem::take(&mut self.probes);
+        for probe in probes.iter_mut() {
+            let mut value = [0.0];
+            self.shepard(&search, probe.pos, &self.field(probe.field), &mut value);
+            probe.series.push((time, value[0]));
+        }
+        self.probes = probes;
+    }
+
+    pub(crate) fn search_grid(&self) -> Grid {
+        let mut search = Grid::default();
+        search.build(&self.params.domain, self.params.inflow_outflow, H, &self.particles.pos);
+        search
+    }
+
+    // SPH interpolation normalized by the kernel sum (Shepard), so that
+    // constant fields are reproduced exactly near free surfaces
+    fn shepard(&self, search: &Grid, x: Vector2<Real>, values: &[Real], out: &mut [Real]) {
+        let components = out.len();
+        let ps = &self.particles;
+        let mut weight = 0.0;
+        for v in out.iter_mut() {
+            *v = 0.0;
+        }
+        for j in search.candidates(x) {
+            let r2 = self.delta(x, ps.pos[j]).magnitude2();
+            // particles emitted this step have no density yet
+            if r2 < H2 && ps.rho[j] > 0.0 {
+                let w = ps.m[j] / ps.rho[j] * POLY6 * (H2 - r2) * (H2 - r2) * (H2 - r2);
+                weight += w;
+                for (c, v) in out.iter_mut().enumerate() {
+                    *v += w * values[j * components + c];
+                }
+            }
+        }
+        for v in out.iter_mut() {
+            *v = if weight > 0.0 { *v / weight } else { Real::NAN };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block() -> Sph {
+        let mut sph = Sph::new(0);
+        sph.particles = crate::block(10, Vector2::new(0.3, 0.3));
+        sph.timestep().unwrap();
+        sph
+    }
+
+    fn grid() -> SampleGrid {
+        SampleGrid { origin: Vector2::new(0.0, 0.0), spacing: Vector2::new(0.1, 0.1), nx: 10, ny: 8 }
+    }
+
+    #[test]
+    fn shepard_reproduces_constants() {
+        let sph = block();
+        let field = sph.sample_values(&grid(), &vec![2.5; sph.particles.len()], 1);
+        assert!((field.get(4, 4)[0] - 2.5).abs() < 1e-5);
+        assert!(field.get(0, 0)[0].is_nan());
+        let covered = field.values.iter().filter(|v| !v.is_nan()).count();
+        assert!(covered > 0 && field.values.iter().all(|v| v.is_nan() || (v - 2.5).abs() < 1e-5));
+
+        let density = sph.sample(&grid(), Field::Density);
+        assert!((density.get(4, 4)[0] - 100.0).abs() < 10.0, "{}", density.get(4, 4)[0]);
+    }
+
+    #[test]
+    fn exports() {
+        let field = block().sample_velocity(&grid());
+        let mut npy = vec![];
+        field.write_npy(&mut npy).unwrap();
+        let header_len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(npy.len(), 10 + header_len + 10 * 8 * 2 * std::mem::size_of::<Real>());
+        assert!(String::from_utf8_lossy(&npy[10..10 + header_len]).contains("'shape': (8, 10, 2)"));
+
+        let mut vtk = vec![];
+        field.write_vtk(&mut vtk, "velocity").unwrap();
+        assert_eq!(String::from_utf8(vtk).unwrap().lines().count(), 10 + 80);
+
+        let mut csv = vec![];
+        field.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 1 + 80);
+    }
+
+    #[test]
+    fn probe_records_every_step() {
+        let mut sph = block();
+        sph.probes.push(Probe::new(Vector2::new(0.4, 0.4), Field::Pressure));
+        for _ in 0..20 {
+            sph.timestep().unwrap();
+        }
+        let series = sph.probes[0].series();
+        assert_eq!(series.len(), 20);
+        assert!(series.iter().all(|(_, p)| p.is_finite()));
+    }
+}
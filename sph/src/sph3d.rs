@@ -0,0 +1,327 @@
+use crate::error::Quantity;
+use crate::finite::{self, Checked, State};
+use crate::grid::Grid;
+use crate::{Field, NonFinitePolicy, Real, Simulation, StepError, PI};
+use crate::{BOUND_DAMPING, DT, GAS_CONST, GRAVITY, REST_DENS, VISC};
+use cgmath::InnerSpace;
+use cgmath::Vector2;
+use cgmath::Vector3;
+use rand::Rng;
+use utils::Instance;
+
+// R is the drawn particle radius, H the kernel support radius, both
+// twice the 2D values to keep the particle count manageable in 3D
+const R: Real = 0.02;
+const H: Real = 0.1;
+const H2: Real = H * H;
+const SPACING: Real = H / 2.0;
+
+// 3D normalizations of the Muller et al. kernels
+const POLY6: Real = 315.0/(64.0 * PI * H2 * H2 * H2 * H2 * H);
+const POLY6_GRAD: Real = -945.0/(32.0 * PI * H2 * H2 * H2 * H2 * H);
+const SPIKY_GRAD: Real = -45.0/(PI * H2 * H2 * H2);
+const MASS: Real = REST_DENS * SPACING * SPACING * SPACING;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Particle3d {
+    id: usize,
+    pos: Vector3<Real>,
+    vel: Vector3<Real>,
+    m: Real,
+    rho: Real,
+    p: Real,
+    f: Vector3<Real>,
+}
+
+impl Particle3d {
+    fn new(id: usize, x: Real, y: Real, z: Real) -> Self {
+        let pos = Vector3::new(x, y, z);
+        let vel = Vector3::new(0.0, 0.0, 0.0);
+        let m = MASS;
+        let rho = 0.0;
+        let p = 0.0;
+        let f = Vector3::new(0.0, 0.0, 0.0);
+        Particle3d { id, pos, vel, m, rho, p, f }
+    }
+}
+
+// Closed box starting at 0,0,0, y is up.
+#[derive(Copy, Clone, Debug)]
+pub struct Box3d {
+    pub width: Real,
+    pub height: Real,
+    pub depth: Real,
+}
+
+impl Default for Box3d {
+    fn default() -> Self {
+        Box3d {
+            width: 1.0,
+            height: 1.0,
+            depth: 1.0,
+        }
+    }
+}
+
+// Newtonian 3D counterpart of `Sph`, with the same weakly compressible
+// pressure, Morris viscosity and damped walls.
+pub struct Sph3d {
+    pub particles: Vec<Particle3d>,
+    pub domain: Box3d,
+    pub gravity: Vector3<Real>,
+    pub viscosity: Real,
+    pub on_non_finite: NonFinitePolicy,
+    steps: usize,
+    grid: Grid,
+    neighbors: Vec<Vec<usize>>,
+}
+
+impl Sph3d {
+    pub fn new(number_instances_per_row: i32) -> Self {
+        let mut particles = vec![];
+
+        let domain = Box3d::default();
+        let mut rng = rand::thread_rng();
+        for i in 0..number_instances_per_row {
+            let x = domain.width / 4.0 + i as Real * SPACING;
+            for j in 0..number_instances_per_row {
+                let y = domain.height / 4.0 + j as Real * SPACING;
+                for k in 0..number_instances_per_row {
+                    let z = domain.depth / 4.0 + k as Real * SPACING;
+                    let jitter = rng.gen_range(-0.05 * SPACING, 0.05 * SPACING);
+                    particles.push(Particle3d::new(particles.len(), x + jitter, y, z));
+                }
+            }
+        }
+
+        Self {
+            particles,
+            domain,
+            gravity: Vector3::new(0.0, GRAVITY, 0.0),
+            viscosity: VISC,
+            on_non_finite: NonFinitePolicy::Abort,
+            steps: 0,
+            grid: Grid::default(),
+            neighbors: vec![],
+        }
+    }
+
+    pub fn find_neighbors(&mut self) {
+        let pos: Vec<Vector3<Real>> = self.particles.iter().map(|p| p.pos).collect();
+        self.grid.build_3d(&self.domain, H, &pos);
+        let mut neighbors = std::mem::take(&mut self.neighbors);
+        neighbors.resize_with(pos.len(), Vec::new);
+        for (i, list) in neighbors.iter_mut().enumerate() {
+            list.clear();
+            for j in self.grid.candidates_3d(pos[i]) {
+                if (pos[j] - pos[i]).magnitude2() < H2 {
+                    list.push(j);
+                }
+            }
+        }
+        self.neighbors = neighbors;
+    }
+
+    pub fn density(&mut self, index: usize) {
+        let mut p = self.particles[index];
+
+        p.rho = 0.0;
+        for &j in &self.neighbors[index] {
+            let pi = self.particles[j];
+            let r2 = (pi.pos - p.pos).magnitude2();
+            if r2 < H2 {
+                p.rho += pi.m * POLY6 * (H2 - r2) * (H2 - r2) * (H2 - r2);
+            }
+        }
+        p.p = (GAS_CONST * (p.rho - REST_DENS)).max(0.0);
+
+        self.particles[index] = p;
+    }
+
+    pub fn forces(&mut self, index: usize) {
+        let mut p = self.particles[index];
+
+        let mut fpress = Vector3::new(0.0, 0.0, 0.0);
+        let mut fvisc = Vector3::new(0.0, 0.0, 0.0);
+        for &j in &self.neighbors[index] {
+            if j == index {
+                continue;
+            }
+            let pi = self.particles[j];
+
+            let rij = pi.pos - p.pos;
+            let r = rij.magnitude();
+
+            if r < H {
+                let grad_w = -rij.normalize() * SPIKY_GRAD * (H - r) * (H - r);
+                let lap_w = POLY6_GRAD * (H2 - r * r) * (H2 - r * r) * r * r / (r * r + 0.01 * H2);
+                fpress += -grad_w * pi.m * (p.p + pi.p)/(2.0 * pi.rho);
+                fvisc += 2.0 * self.viscosity * pi.m * (p.vel - pi.vel)/pi.rho * lap_w;
+            }
+        }
+        p.f = fpress + fvisc + self.gravity * p.rho;
+
+        self.particles[index] = p;
+    }
+
+    pub fn integrate(&mut self, index: usize) {
+        let mut p = self.particles[index];
+
+        p.vel += DT*p.f/p.rho;
+        p.pos += DT*p.vel;
+
+        let size = [self.domain.width, self.domain.height, self.domain.depth];
+        for (axis, &size) in size.iter().enumerate() {
+            if p.pos[axis] - R < 0.0 {
+                p.vel[axis] *= BOUND_DAMPING;
+                p.pos[axis] = R;
+            } else if p.pos[axis] + R > size {
+                p.vel[axis] *= BOUND_DAMPING;
+                p.pos[axis] = size - R;
+            }
+        }
+
+        self.particles[index] = p;
+    }
+
+    pub fn timestep(&mut self) -> Result<(), StepError> {
+        self.steps += 1;
+        self.find_neighbors();
+        for p in 0..self.particles.len() {
+            self.density(p);
+        }
+        for p in 0..self.particles.len() {
+            self.forces(p);
+        }
+        finite::check(self, &[Quantity::Density, Quantity::Pressure, Quantity::Force], &[])?;
+        let old_pos: Vec<Vector3<Real>> = match self.on_non_finite {
+            NonFinitePolicy::Clamp => self.particles.iter().map(|p| p.pos).collect(),
+            _ => vec![],
+        };
+        for p in 0..self.particles.len() {
+            self.integrate(p);
+        }
+        finite::check(self, &[Quantity::Velocity, Quantity::Position], &old_pos)
+    }
+}
+
+// there is no temperature in 3D
+impl Checked for Sph3d {
+    type Vector = Vector3<Real>;
+
+    fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn policy(&self) -> NonFinitePolicy {
+        self.on_non_finite
+    }
+
+    fn reference_temperature(&self) -> Real {
+        0.0
+    }
+
+    fn count(&self) -> usize {
+        self.particles.len()
+    }
+
+    fn state(&mut self, index: usize) -> State<'_, Vector3<Real>> {
+        let p = &mut self.particles[index];
+        State { id: p.id, rho: &mut p.rho, p: &mut p.p, f: &mut p.f, vel: &mut p.vel, pos: &mut p.pos, t: None }
+    }
+
+    fn keep(&mut self, kept: &[usize]) {
+        self.particles = kept.iter().map(|&i| self.particles[i]).collect();
+    }
+}
+
+impl Simulation for Sph3d {
+    fn timestep(&mut self) -> Result<(), StepError> {
+        Sph3d::timestep(self)
+    }
+
+    // centered on the box, which is scaled uniformly so its longest side
+    // spans [-1, 1]
+    fn instances(&self) -> Vec<Instance> {
+        let domain = self.domain;
+        let s = 2.0 / domain.width.max(domain.height).max(domain.depth);
+        self.particles
+            .iter()
+            .map(|p| Instance {
+                position: Vector3 {
+                    x: ((p.pos.x - 0.5 * domain.width) * s) as f32,
+                    y: ((p.pos.y - 0.5 * domain.height) * s) as f32,
+                    z: ((p.pos.z - 0.5 * domain.depth) * s) as f32,
+                },
+                rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+                scale: Vector2 { x: (R * s) as f32, y: (R * s) as f32 },
+                // no surface detection in 3D yet
+                surface: false,
+                normal: Vector2 { x: 0.0, y: 0.0 },
+                scalar: 0.0,
+            })
+            .collect()
+    }
+
+    // single phase and isothermal, the other fields are zero
+    fn scalars(&self, field: Field) -> Vec<f32> {
+        self.particles
+            .iter()
+            .map(|p| match field {
+                Field::Speed => p.vel.magnitude() as f32,
+                Field::Density => p.rho as f32,
+                Field::Pressure => p.p as f32,
+                _ => 0.0,
+            })
+            .collect()
+    }
+
+    fn is_3d(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_settles_in_box() {
+        // the block starts at y = 0.25 and spreads over the floor
+        let mut sph = Sph3d::new(6);
+        for _ in 0..600 {
+            sph.timestep().unwrap();
+        }
+        for p in &sph.particles {
+            assert!(p.pos.x.is_finite() && p.pos.y.is_finite() && p.pos.z.is_finite());
+            assert!(p.pos.y < 0.25, "{:?}", p.pos);
+        }
+        let rho = sph.particles.iter().map(|p| p.rho).sum::<Real>() / sph.particles.len() as Real;
+        assert!(rho > 0.5 * REST_DENS && rho < 1.5 * REST_DENS, "{}", rho);
+    }
+
+    #[test]
+    fn non_finite_policies() {
+        let broken = |policy| {
+            let mut sph = Sph3d::new(2);
+            let mut p = Particle3d::new(8, 0.9, 0.9, 0.9);
+            p.m = 0.0;
+            sph.particles.push(p);
+            sph.on_non_finite = policy;
+            sph
+        };
+        finite::check_policies(broken, 8);
+    }
+
+    #[test]
+    fn instances_keep_the_aspect_ratio() {
+        let mut sph = Sph3d::new(0);
+        sph.domain = Box3d { width: 2.0, height: 1.0, depth: 0.5 };
+        sph.particles.push(Particle3d::new(0, 2.0, 1.0, 0.5));
+        sph.particles.push(Particle3d::new(1, 1.0, 0.5, 0.25));
+        let instances = sph.instances();
+        assert_eq!(instances[0].position, Vector3::new(1.0, 0.5, 0.25));
+        assert_eq!(instances[1].position, Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(instances[0].scale, Vector2::new(R as f32, R as f32));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::Real;
+use cgmath::Vector2;
+use std::fmt;
+use std::io::{self, Write};
+
+// Health of the simulation after a step. Density errors are relative to
+// the rest density, potential energy is measured from the origin along
+// gravity and angular momentum is taken about the origin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StepStats {
+    pub step: usize,
+    pub time: Real,
+    pub kinetic_energy: Real,
+    pub potential_energy: Real,
+    pub momentum: Vector2<Real>,
+    pub angular_momentum: Real,
+    pub max_density_error: Real,
+    pub avg_density_error: Real,
+    pub max_velocity: Real,
+    pub min_neighbors: usize,
+    pub cfl: Real,
+}
+
+impl StepStats {
+    pub fn write_csv_header<W: Write>(mut w: W) -> io::Result<()> {
+        writeln!(
+            w,
+            "step,time,kinetic_energy,potential_energy,momentum_x,momentum_y,angular_momentum,\
+             max_density_error,avg_density_error,max_velocity,min_neighbors,cfl"
+        )
+    }
+
+    pub fn write_csv_row<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.step,
+            self.time,
+            self.kinetic_energy,
+            self.potential_energy,
+            self.momentum.x,
+            self.momentum.y,
+            self.angular_momentum,
+            self.max_density_error,
+            self.avg_density_error,
+            self.max_velocity,
+            self.min_neighbors,
+            self.cfl
+        )
+    }
+}
+
+impl fmt::Display for StepStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "step {} t={:.4} ke={:.4e} pe={:.4e} p=({:.3e}, {:.3e}) L={:.3e} \
+             rho_err max={:.3} avg={:.3} v_max={:.3} min_nb={} cfl={:.3}",
+            self.step,
+            self.time,
+            self.kinetic_energy,
+            self.potential_energy,
+            self.momentum.x,
+            self.momentum.y,
+            self.angular_momentum,
+            self.max_density_error,
+            self.avg_density_error,
+            self.max_velocity,
+            self.min_neighbors,
+            self.cfl
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::{Emitter, Shape, Sink};
+    use crate::Sph;
+    use std::sync::{Arc, Mutex};
+
+    // clones write to the same bytes, so the test can read what the
+    // solver's copy wrote
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn free_fall_momentum() {
+        let mut sph = Sph::new(8);
+        let buffer = SharedBuffer::default();
+        sph.write_stats_csv(buffer.clone()).unwrap();
+        for _ in 0..100 {
+            sph.timestep().unwrap();
+        }
+        let stats = sph.stats();
+        let mass: Real = sph.particles.iter().map(|p| p.m).sum();
+        // pressure and viscosity are internal, only gravity changes momentum
+        let expected = mass * sph.params.gravity.y * stats.time;
+        assert!((stats.momentum.y - expected).abs() < 0.01 * expected.abs(), "{:?}", stats);
+        assert!(stats.kinetic_energy > 0.0 && stats.min_neighbors > 0 && stats.cfl < 1.0);
+
+        let csv = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(csv.lines().count(), 101);
+        assert!(csv.lines().last().unwrap().starts_with("100,"));
+    }
+
+    #[test]
+    fn particles_added_or_removed_in_a_step() {
+        let mut sph = Sph::new(8);
+        // particles are emitted just above the block in most steps, and a
+        // sink takes its bottom rows
+        let nozzle = Shape::Nozzle { center: Vector2::new(0.34, 0.46), width: 0.1 };
+        sph.emitters.push(Emitter::new(nozzle, 500.0, Vector2::new(0.0, -3.0)));
+        sph.sinks.push(Sink { min: Vector2::new(0.0, 0.0), max: Vector2::new(1.0, 0.3) });
+        for _ in 0..20 {
+            sph.timestep().unwrap();
+            let stats = sph.stats();
+            assert!(stats.min_neighbors > 0, "{:?}", stats);
+            // a new particle has no density yet
+            assert!(stats.max_density_error < 0.9, "{:?}", stats);
+        }
+        assert!(sph.particles.len() < 64);
+    }
+}
@@ -0,0 +1,40 @@
+use crate::Real;
+use cgmath::Vector2;
+
+// Axis aligned box that holds every particle inside it at a fixed
+// temperature, used for heated and cooled walls.
+#[derive(Copy, Clone, Debug)]
+pub struct Region {
+    pub min: Vector2<Real>,
+    pub max: Vector2<Real>,
+    pub temperature: Real,
+}
+
+impl Region {
+    pub fn contains(&self, pos: Vector2<Real>) -> bool {
+        pos.x >= self.min.x && pos.x <= self.max.x && pos.y >= self.min.y && pos.y <= self.max.y
+    }
+}
+
+// Heat conduction with Brookshaw's Laplacian and Boussinesq buoyancy,
+// gravity is scaled by 1 - expansion * (T - reference). Fixed particles
+// inside a region are walls at its temperature, the other fixed particles
+// exchange no heat, which makes them adiabatic walls.
+#[derive(Clone, Debug)]
+pub struct Thermal {
+    pub diffusivity: Real,
+    pub expansion: Real,
+    pub reference: Real,
+    pub regions: Vec<Region>,
+}
+
+impl Default for Thermal {
+    fn default() -> Self {
+        Thermal {
+            diffusivity: 0.01,
+            expansion: 0.0,
+            reference: 0.0,
+            regions: vec![],
+        }
+    }
+}
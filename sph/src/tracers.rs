@@ -0,0 +1,94 @@
+use crate::{Particles, Real};
+use cgmath::Vector2;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub time: Real,
+    pub pos: Vector2<Real>,
+    pub vel: Vector2<Real>,
+    pub rho: Real,
+}
+
+// Trajectories of tagged particles, keyed by particle id. A trajectory
+// stops growing when its particle is removed.
+#[derive(Clone, Debug, Default)]
+pub struct Tracers {
+    trajectories: BTreeMap<usize, Vec<Sample>>,
+}
+
+impl Tracers {
+    pub fn tag(&mut self, id: usize) {
+        self.trajectories.entry(id).or_default();
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.trajectories.keys().cloned()
+    }
+
+    pub fn trajectory(&self, id: usize) -> Option<&[Sample]> {
+        self.trajectories.get(&id).map(|t| t.as_slice())
+    }
+
+    pub(crate) fn record(&mut self, time: Real, particles: &Particles) {
+        if self.trajectories.is_empty() {
+            return;
+        }
+        for i in 0..particles.len() {
+            if let Some(trajectory) = self.trajectories.get_mut(&particles.id[i]) {
+                trajectory.push(Sample {
+                    time,
+                    pos: particles.pos[i],
+                    vel: particles.vel[i],
+                    rho: particles.rho[i],
+                });
+            }
+        }
+    }
+
+    // one row per sample, grouped by id
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "id,time,x,y,vx,vy,rho")?;
+        for (id, trajectory) in &self.trajectories {
+            for s in trajectory {
+                writeln!(
+                    w,
+                    "{},{},{},{},{},{},{}",
+                    id, s.time, s.pos.x, s.pos.y, s.vel.x, s.vel.y, s.rho
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Sph;
+    use cgmath::InnerSpace;
+
+    #[test]
+    fn tracer_follows_falling_particle() {
+        let mut sph = Sph::new(6);
+        sph.params.reorder_every = Some(5);
+        let id = sph.trace(35);
+        for _ in 0..50 {
+            sph.timestep().unwrap();
+        }
+        let trajectory = sph.tracers.trajectory(id).unwrap();
+        assert_eq!(trajectory.len(), 50);
+        // samples stay continuous across reorders
+        for s in trajectory.windows(2) {
+            assert!(s[1].time > s[0].time);
+            assert!((s[1].pos - s[0].pos).magnitude() < 0.001);
+        }
+        assert!(trajectory[49].pos.y < trajectory[0].pos.y);
+
+        let mut csv = vec![];
+        sph.tracers.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 51);
+        assert!(csv.lines().nth(1).unwrap().starts_with(&format!("{},", id)));
+    }
+}
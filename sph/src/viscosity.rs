@@ -0,0 +1,139 @@
+use crate::Real;
+
+// Shear rates below this are treated as this value so that shear-thinning
+// models and the Bingham regularization stay finite for fluid at rest.
+const MIN_SHEAR_RATE: Real = 1e-3;
+
+pub trait Viscosity {
+    fn viscosity(&self, shear_rate: Real) -> Real;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Newtonian {
+    pub mu: Real,
+}
+
+impl Viscosity for Newtonian {
+    fn viscosity(&self, _shear_rate: Real) -> Real {
+        self.mu
+    }
+}
+
+// mu = k * shear_rate^(n - 1), shear thinning for n < 1 and thickening for n > 1
+#[derive(Copy, Clone, Debug)]
+pub struct PowerLaw {
+    pub k: Real,
+    pub n: Real,
+}
+
+impl Viscosity for PowerLaw {
+    fn viscosity(&self, shear_rate: Real) -> Real {
+        self.k * shear_rate.max(MIN_SHEAR_RATE).powf(self.n - 1.0)
+    }
+}
+
+// mu = mu_inf + (mu_0 - mu_inf) / (1 + (lambda * shear_rate)^n)
+#[derive(Copy, Clone, Debug)]
+pub struct Cross {
+    pub mu_0: Real,
+    pub mu_inf: Real,
+    pub lambda: Real,
+    pub n: Real,
+}
+
+impl Viscosity for Cross {
+    fn viscosity(&self, shear_rate: Real) -> Real {
+        self.mu_inf + (self.mu_0 - self.mu_inf) / (1.0 + (self.lambda * shear_rate).powf(self.n))
+    }
+}
+
+// mu = mu_inf + (mu_0 - mu_inf) * (1 + (lambda * shear_rate)^2)^((n - 1) / 2)
+#[derive(Copy, Clone, Debug)]
+pub struct Carreau {
+    pub mu_0: Real,
+    pub mu_inf: Real,
+    pub lambda: Real,
+    pub n: Real,
+}
+
+impl Viscosity for Carreau {
+    fn viscosity(&self, shear_rate: Real) -> Real {
+        let x = 1.0 + (self.lambda * shear_rate) * (self.lambda * shear_rate);
+        self.mu_inf + (self.mu_0 - self.mu_inf) * x.powf((self.n - 1.0) / 2.0)
+    }
+}
+
+// Papanastasiou regularization of the Bingham plastic:
+// mu = mu_p + yield_stress * (1 - exp(-m * shear_rate)) / shear_rate
+// Larger m gets closer to the ideal rigid/flowing switch at the yield stress.
+#[derive(Copy, Clone, Debug)]
+pub struct Bingham {
+    pub mu_p: Real,
+    pub yield_stress: Real,
+    pub m: Real,
+}
+
+impl Viscosity for Bingham {
+    fn viscosity(&self, shear_rate: Real) -> Real {
+        let shear_rate = shear_rate.max(MIN_SHEAR_RATE);
+        self.mu_p + self.yield_stress * (1.0 - (-self.m * shear_rate).exp()) / shear_rate
+    }
+}
+
+// Send and Sync so that a solver holding phases can move to another thread.
+pub struct Phase {
+    pub viscosity: Box<dyn Viscosity + Send + Sync>,
+}
+
+impl Phase {
+    pub fn new<V: Viscosity + Send + Sync + 'static>(viscosity: V) -> Self {
+        Phase {
+            viscosity: Box::new(viscosity),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newtonian_is_constant() {
+        let v = Newtonian { mu: 2.0 };
+        assert_eq!(v.viscosity(0.0), 2.0);
+        assert_eq!(v.viscosity(100.0), 2.0);
+    }
+
+    #[test]
+    fn power_law_thins_and_thickens() {
+        let thinning = PowerLaw { k: 1.0, n: 0.5 };
+        assert!(thinning.viscosity(10.0) < thinning.viscosity(1.0));
+        assert!(thinning.viscosity(0.0).is_finite());
+
+        let thickening = PowerLaw { k: 1.0, n: 1.5 };
+        assert!(thickening.viscosity(10.0) > thickening.viscosity(1.0));
+    }
+
+    #[test]
+    fn cross_and_carreau_plateaus() {
+        let cross = Cross { mu_0: 10.0, mu_inf: 1.0, lambda: 1.0, n: 1.0 };
+        let carreau = Carreau { mu_0: 10.0, mu_inf: 1.0, lambda: 1.0, n: 0.5 };
+        for v in [&cross as &dyn Viscosity, &carreau].iter() {
+            assert!((v.viscosity(0.0) - 10.0).abs() < 1e-4);
+            assert!(v.viscosity(1e6) < 1.1);
+        }
+    }
+
+    #[test]
+    fn phase_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Phase>();
+    }
+
+    #[test]
+    fn bingham_is_stiff_below_yield() {
+        let v = Bingham { mu_p: 1.0, yield_stress: 5.0, m: 100.0 };
+        assert!(v.viscosity(0.0) > 100.0 * v.viscosity(100.0));
+        assert!((v.viscosity(1e4) - 1.0).abs() < 1e-2);
+    }
+}
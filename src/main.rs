@@ -1,9 +1,10 @@
 extern crate scene;
 
-use crate::scene::State;
+use crate::scene::{Recorder, State};
 use futures::executor::block_on;
 
-use sph::Sph;
+use sph::{Simulation, Sph, Sph3d};
+use utils::InstanceLayout;
 
 use winit::{
     event::*,
@@ -12,40 +13,105 @@ use winit::{
 };
 
 const NUM_INSTANCES_PER_ROW: i32 = 20;
+const NUM_INSTANCES_PER_ROW_3D: i32 = 8;
+
+// value following `name` on the command line
+fn arg_value(name: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let i = args.iter().position(|arg| arg == name)?;
+    args.get(i + 1).cloned()
+}
+
+// Headless run writing numbered PNG frames, e.g.
+// --record frames --size 1280x720 --every 10 --steps 2000
+fn record(mut model: Box<dyn Simulation>, dir: String) {
+    let (width, height) = arg_value("--size")
+        .and_then(|size| {
+            let (w, h) = size.split_at(size.find('x')?);
+            Some((w.parse().ok()?, h[1..].parse().ok()?))
+        })
+        .unwrap_or((1280, 720));
+    let every = arg_value("--every").and_then(|n| n.parse().ok()).unwrap_or(10);
+    let steps = arg_value("--steps").and_then(|n| n.parse().ok()).unwrap_or(1000);
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("cannot create {}: {}", dir, e);
+        return;
+    }
+    let mut recorder = block_on(Recorder::new(dir, width, height, every, instance_layout()));
+    if !recorder.is_gpu() {
+        eprintln!("no graphics adapter, drawing frames on the CPU");
+    }
+    for _ in 0..steps {
+        if let Err(e) = model.timestep() {
+            eprintln!("simulation stopped: {}", e);
+            break;
+        }
+        if let Err(e) = recorder.record(&*model) {
+            eprintln!("cannot write frame: {}", e);
+            break;
+        }
+    }
+}
+
+// --compact sends a fifth of the data per particle, without rotation or
+// surface flags, for runs with very many particles
+fn instance_layout() -> InstanceLayout {
+    if std::env::args().any(|arg| arg == "--compact") {
+        InstanceLayout::Compact
+    } else {
+        InstanceLayout::Full
+    }
+}
+
+fn model() -> Box<dyn Simulation> {
+    if std::env::args().any(|arg| arg == "--3d") {
+        Box::new(Sph3d::new(NUM_INSTANCES_PER_ROW_3D))
+    } else {
+        Box::new(Sph::new(NUM_INSTANCES_PER_ROW))
+    }
+}
 
 fn main() {
     env_logger::init();
+    if let Some(dir) = arg_value("--record") {
+        return record(model(), dir);
+    }
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
-    let model = Sph::new(NUM_INSTANCES_PER_ROW);
-    let mut state = block_on(State::new(&window, model));
+    let mut state = match block_on(State::new(&window, model(), instance_layout())) {
+        Ok(state) => state,
+        // nothing has run yet, the headless run starts from a fresh model
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("running without a window instead, writing frames to ./frames");
+            drop(window);
+            return record(model(), "frames".to_string());
+        }
+    };
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == window.id() => {
-            if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::KeyboardInput { input, .. } => match input {
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
-                            ..
-                        } => *control_flow = ControlFlow::Exit,
-                        _ => {}
+        } if window_id == window.id() && !state.input(event) => match event {
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Escape),
+                        ..
                     },
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(**new_inner_size);
-                    }
-                    _ => {}
-                }
+                ..
+            } => *control_flow = ControlFlow::Exit,
+            WindowEvent::Resized(physical_size) => {
+                state.resize(*physical_size);
             }
-        }
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                state.resize(**new_inner_size);
+            }
+            _ => {}
+        },
         Event::RedrawRequested(_) => {
             state.update();
             match state.render() {
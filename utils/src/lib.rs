@@ -11,15 +11,48 @@ pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
     pub scale: cgmath::Vector2<f32>,
+    // free surface flag and outward normal, in simulation axes
+    pub surface: bool,
+    pub normal: cgmath::Vector2<f32>,
+    // value mapped through the colormap
+    pub scalar: f32,
 }
 
 impl Instance {
-    pub fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
-            scale: self.scale.into(),
+    // the compact layout ignores rotation, the normal and the surface
+    // flag, and takes the radius from scale.x
+    pub fn to_raw(&self, layout: InstanceLayout) -> Raw {
+        match layout {
+            InstanceLayout::Full => Raw::Full(InstanceRaw {
+                model: (cgmath::Matrix4::from_translation(self.position)
+                    * cgmath::Matrix4::from(self.rotation))
+                .into(),
+                scale: self.scale.into(),
+                normal: self.normal.into(),
+                surface: if self.surface { 1.0 } else { 0.0 },
+                scalar: self.scalar,
+            }),
+            InstanceLayout::Compact => Raw::Compact(InstanceCompact {
+                position: self.position.into(),
+                radius: self.scale.x,
+                scalar: self.scalar,
+            }),
+        }
+    }
+}
+
+// An instance in the layout a pipeline reads, ready for upload.
+#[derive(Copy, Clone)]
+pub enum Raw {
+    Full(InstanceRaw),
+    Compact(InstanceCompact),
+}
+
+impl Raw {
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Raw::Full(raw) => bytemuck::bytes_of(raw),
+            Raw::Compact(compact) => bytemuck::bytes_of(compact),
         }
     }
 }
@@ -29,6 +62,9 @@ impl Instance {
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
     pub scale: [f32; 2],
+    pub normal: [f32; 2],
+    pub surface: f32,
+    pub scalar: f32,
 }
 
 unsafe impl bytemuck::Pod for InstanceRaw {}
@@ -65,6 +101,21 @@ impl InstanceRaw {
                     offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
                     shader_location: 9,
                     format: wgpu::VertexFormat::Float2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 21]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float,
                 }
             ],
         }
@@ -94,8 +145,91 @@ impl Vertex {
     }
 }
 
+// Position, radius and color scalar, for particles that are never
+// rotated or stretched. 20 bytes instead of the 88 of InstanceRaw.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct InstanceCompact {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub scalar: f32,
+}
+
+unsafe impl bytemuck::Pod for InstanceCompact {}
+unsafe impl bytemuck::Zeroable for InstanceCompact {}
+
+impl InstanceCompact {
+    pub fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        use std::mem;
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<InstanceCompact>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float,
+                },
+            ],
+        }
+    }
+}
+
+// Which of the two instance formats a pipeline reads. The vertex shaders
+// come in a variant for each.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InstanceLayout {
+    Full,
+    Compact,
+}
+
+impl InstanceLayout {
+    pub fn desc<'a>(self) -> wgpu::VertexBufferDescriptor<'a> {
+        match self {
+            InstanceLayout::Full => InstanceRaw::desc(),
+            InstanceLayout::Compact => InstanceCompact::desc(),
+        }
+    }
+    pub fn size(self) -> usize {
+        match self {
+            InstanceLayout::Full => std::mem::size_of::<InstanceRaw>(),
+            InstanceLayout::Compact => std::mem::size_of::<InstanceCompact>(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_keeps_position_radius_and_scalar() {
+        let instance = Instance {
+            position: cgmath::Vector3::new(1.0, 2.0, 3.0),
+            rotation: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: cgmath::Vector2::new(0.5, 0.5),
+            surface: false,
+            normal: cgmath::Vector2::new(0.0, 0.0),
+            scalar: 7.0,
+        };
+        let compact = instance.to_raw(InstanceLayout::Compact);
+        let floats: &[f32] = bytemuck::cast_slice(compact.bytes());
+        assert_eq!(floats, &[1.0, 2.0, 3.0, 0.5, 7.0]);
+        assert_eq!(InstanceLayout::Compact.size(), 20);
+        assert_eq!(instance.to_raw(InstanceLayout::Full).bytes().len(), InstanceLayout::Full.size());
+        assert_eq!(InstanceLayout::Full.size(), 88);
+    }
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);